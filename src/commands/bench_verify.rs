@@ -0,0 +1,112 @@
+use std::time::Instant;
+
+use rust_decimal::Decimal;
+use sha2::{Digest, Sha256};
+use sp_core::{ecdsa, Pair};
+
+use crate::handlers::{
+    recieved_block::{check_tx_hashes, check_txs_crypto, create_hash},
+    structures::{
+        InputData, OutputData, OutputUnspent, OutputUtxo, Transaction, TransactionScript, TxInput,
+        TxOutput, UtxoData,
+    },
+};
+
+const DEFAULT_TXS: usize = 2000;
+
+//relay-node bench-verify [--txs N]
+//throughput of verifying signatures and hashes of transactions one by one and in parallel
+pub fn bench_verify(args: &[String]) -> Result<(), String> {
+    let txs_len = match args {
+        [] => DEFAULT_TXS,
+        [flag, value] if flag == "--txs" => value
+            .parse::<usize>()
+            .map_err(|_| "--txs needs a number".to_string())?,
+        _ => return Err("usage: bench-verify [--txs N]".to_string()),
+    };
+
+    println!("making {} signed transactions", txs_len);
+    let txs: Vec<Transaction> = (0..txs_len).map(signed_tx).collect();
+
+    let start = Instant::now();
+    let sequential = txs.iter().all(check_tx_hashes);
+    let sequential_time = start.elapsed();
+
+    let start = Instant::now();
+    let parallel = check_txs_crypto(&txs);
+    let parallel_time = start.elapsed();
+
+    if !sequential || !parallel {
+        return Err("verifying transactions problem".to_string());
+    }
+    let throughput = |secs: f64| txs_len as f64 / secs.max(f64::EPSILON);
+    println!(
+        "sequential: {:?} ({:.0} tx/s)",
+        sequential_time,
+        throughput(sequential_time.as_secs_f64())
+    );
+    println!(
+        "parallel ({} threads): {:?} ({:.0} tx/s)",
+        rayon::current_num_threads(),
+        parallel_time,
+        throughput(parallel_time.as_secs_f64())
+    );
+    println!(
+        "speedup: {:.2}x",
+        sequential_time.as_secs_f64() / parallel_time.as_secs_f64().max(f64::EPSILON)
+    );
+    Ok(())
+}
+
+//a transaction with one input and one output that is signed like wallets sign it
+fn signed_tx(i: usize) -> Transaction {
+    let (pair, _) = ecdsa::Pair::generate();
+    let public_key = pair.public();
+
+    let input_data = InputData {
+        number: 1,
+        utxos: vec![UtxoData {
+            transaction_hash: create_hash(format!("bench tx {}", i)),
+            unspent: Decimal::from(10),
+            output_hash: create_hash(format!("bench output {}", i)),
+            block_number: 0,
+        }],
+        script: TransactionScript::SingleSig,
+    };
+    let output_data = OutputData {
+        number: 1,
+        utxos: vec![OutputUtxo {
+            hash: create_hash(format!("bench new output {}", i)),
+            output_unspent: OutputUnspent {
+                public_key: public_key.to_string(),
+                unspent: Decimal::from(9),
+                rnum: i as u32,
+            },
+        }],
+        sigenr_public_keys: vec![public_key],
+    };
+    let input_hash = create_hash(serde_json::to_string(&input_data).unwrap());
+    let output_hash = create_hash(serde_json::to_string(&output_data).unwrap());
+
+    let mut hasher = Sha256::new();
+    hasher.update(input_hash.clone());
+    hasher.update(output_hash.clone());
+    let tx_hash = format!("{:x}", hasher.finalize());
+    let signature = pair.sign(tx_hash.as_bytes());
+
+    Transaction {
+        tx_hash,
+        input: TxInput {
+            input_hash,
+            input_data,
+            signatures: vec![signature],
+        },
+        output: TxOutput {
+            output_hash,
+            output_data,
+        },
+        value: Decimal::from(10),
+        fee: Decimal::from(1),
+        date: String::new(),
+    }
+}
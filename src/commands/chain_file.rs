@@ -0,0 +1,225 @@
+// Chain file format (version 1)
+//
+//   magic      - 8 bytes "CENTICHN"
+//   version    - u32 big endian
+//   network id - u32 big endian length + utf8 network id of the chain spec
+//   records    - u32 big endian length + json of a Block, ordered by header.number
+//
+// Blocks are json because block hashes are made from the json of their body.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use futures::StreamExt;
+use mongodb::{
+    bson::{doc, from_document, Document},
+    options::FindOptions,
+    Collection,
+};
+
+use crate::handlers::{
+    block_sync::local_tip,
+    chain_spec::{CHAIN_SPEC, GENESIS_PREVHASH},
+    create_log::write_log,
+    db_connection::blockchain_db,
+    recieved_block::{check_block_sign, create_hash, insert_synced_block},
+    structures::Block,
+};
+
+const MAGIC: &[u8; 8] = b"CENTICHN";
+const CHAIN_FILE_VERSION: u32 = 1;
+//a block record can't be bigger than this
+const MAX_RECORD: u32 = 64 * 1024 * 1024;
+
+fn write_record(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(data)
+}
+
+//read a length prefixed record, none at the end of file (a part of a length is an error)
+fn read_record(reader: &mut impl Read) -> Result<Option<Vec<u8>>, String> {
+    let mut len = [0u8; 4];
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err("length of record is not complete".to_string()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_RECORD {
+        return Err(format!("record of {} bytes is too big", len));
+    }
+    let mut data = vec![0u8; len as usize];
+    reader.read_exact(&mut data).map_err(|e| e.to_string())?;
+    Ok(Some(data))
+}
+
+//relay-node export-chain <file> [--from N] [--to N]
+pub async fn export_chain(path: &str, from: Option<i64>, to: Option<i64>) -> Result<(), String> {
+    let db = blockchain_db().await?;
+    let blocks_coll: Collection<Document> = db.collection("Blocks");
+
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(MAGIC).map_err(|e| e.to_string())?;
+    writer
+        .write_all(&CHAIN_FILE_VERSION.to_be_bytes())
+        .map_err(|e| e.to_string())?;
+    write_record(&mut writer, CHAIN_SPEC.network_id.as_bytes()).map_err(|e| e.to_string())?;
+
+    let filter = doc! {"header.number": {
+        "$gte": from.unwrap_or(i64::MIN),
+        "$lte": to.unwrap_or(i64::MAX),
+    }};
+    let option = FindOptions::builder()
+        .sort(doc! {"header.number": 1})
+        .build();
+    let mut blocks = blocks_coll
+        .find(filter, option)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut exported = 0;
+    while let Some(doc) = blocks.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        let block: Block = from_document(doc).map_err(|e| e.to_string())?;
+        let str_block = serde_json::to_vec(&block).map_err(|e| e.to_string())?;
+        write_record(&mut writer, &str_block).map_err(|e| e.to_string())?;
+        exported += 1;
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+
+    println!("{} blocks exported to {}", exported, path);
+    write_log(&format!("{} blocks exported to {}", exported, path));
+    Ok(())
+}
+
+//relay-node import-chain <file> [--from N] [--to N]
+//blocks are added to the end of our chain after full validation, blocks that we have are skipped
+pub async fn import_chain(path: &str, from: Option<i64>, to: Option<i64>) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+    if &magic != MAGIC {
+        return Err("file is not a chain file".to_string());
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version).map_err(|e| e.to_string())?;
+    if u32::from_be_bytes(version) != CHAIN_FILE_VERSION {
+        return Err(format!(
+            "unknown chain file version: {}",
+            u32::from_be_bytes(version)
+        ));
+    }
+    let network_id = match read_record(&mut reader)? {
+        Some(network_id) => String::from_utf8(network_id).map_err(|e| e.to_string())?,
+        None => return Err("there is not network id in chain file".to_string()),
+    };
+    if network_id != CHAIN_SPEC.network_id {
+        return Err(format!(
+            "chain file is for {} network, not {}",
+            network_id, CHAIN_SPEC.network_id
+        ));
+    }
+
+    let (mut tip_hash, mut tip_number) = match local_tip().await {
+        Ok(Some(tip)) => (tip.blockhash, tip.number),
+        Ok(None) => (GENESIS_PREVHASH.to_string(), -1),
+        Err(_) => return Err("finding last block problem".to_string()),
+    };
+    let (mut imported, mut skipped) = (0, 0);
+    while let Some(record) = read_record(&mut reader)? {
+        let block: Block = serde_json::from_slice(&record).map_err(|e| e.to_string())?;
+        let number = block.header.number;
+        if from.is_some_and(|from| number < from) {
+            continue;
+        }
+        if to.is_some_and(|to| number > to) {
+            break;
+        }
+        if number <= tip_number {
+            skipped += 1;
+            continue;
+        }
+
+        //same checks as recieved blocks, then inputs and outputs with our UTXOs
+        if block.header.prevhash != tip_hash {
+            return Err(format!("block {} is not linked to our last block", number));
+        }
+        if block.header.prevhash == GENESIS_PREVHASH
+            && !CHAIN_SPEC.is_genesis(&block.header.blockhash)
+        {
+            return Err("genesis is not the genesis of chain spec".to_string());
+        }
+        let str_block_body = serde_json::to_string(&block.body).map_err(|e| e.to_string())?;
+        if create_hash(str_block_body) != block.header.blockhash {
+            return Err(format!("block {} hash problem", number));
+        }
+        if let Err(e) = check_block_sign(&block) {
+            return Err(format!("block {}: {}", number, e));
+        }
+        if !CHAIN_SPEC.check_reward(&block) {
+            return Err(format!("block {} reward problem", number));
+        }
+        tip_hash = block.header.blockhash.clone();
+        tip_number = number;
+        if insert_synced_block(block).await.is_err() {
+            return Err(format!("inserting block {} problem", number));
+        }
+
+        imported += 1;
+        if imported % 1000 == 0 {
+            println!("{} blocks imported", imported);
+        }
+    }
+
+    println!(
+        "{} blocks imported, {} blocks were in database already",
+        imported, skipped
+    );
+    write_log(&format!("{} blocks imported from {}", imported, path));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let mut file = Vec::new();
+        write_record(&mut file, b"devnet").unwrap();
+        write_record(&mut file, b"").unwrap();
+        write_record(&mut file, br#"{"header":{}}"#).unwrap();
+
+        let mut reader = file.as_slice();
+        assert_eq!(read_record(&mut reader), Ok(Some(b"devnet".to_vec())));
+        assert_eq!(read_record(&mut reader), Ok(Some(Vec::new())));
+        assert_eq!(
+            read_record(&mut reader),
+            Ok(Some(br#"{"header":{}}"#.to_vec()))
+        );
+        assert_eq!(read_record(&mut reader), Ok(None));
+    }
+
+    #[test]
+    fn truncated_records_are_errors() {
+        let mut file = Vec::new();
+        write_record(&mut file, b"block").unwrap();
+        assert!(read_record(&mut &file[..file.len() - 1]).is_err());
+        assert!(read_record(&mut &file[..2]).is_err());
+    }
+
+    #[test]
+    fn big_records_are_errors() {
+        let file = (MAX_RECORD + 1).to_be_bytes();
+        assert!(read_record(&mut file.as_slice()).is_err());
+    }
+}
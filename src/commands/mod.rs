@@ -0,0 +1,93 @@
+mod bench_verify;
+mod chain_file;
+mod reindex;
+mod reset_db;
+mod verify_db;
+
+use crate::handlers::create_log::write_log;
+
+//run a command of relay-node (relay-node <command> [args]) instead of the relay
+//commands change the database, so the relay must be stopped while they run
+//returns exit code of the command
+pub async fn run_command(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "bench-verify" => bench_verify::bench_verify(&args[1..]),
+        "export-chain" | "import-chain" => match chain_file_args(&args[1..]) {
+            Ok((path, from, to)) if args[0] == "export-chain" => {
+                chain_file::export_chain(&path, from, to).await
+            }
+            Ok((path, from, to)) => chain_file::import_chain(&path, from, to).await,
+            Err(e) => Err(e),
+        },
+        "reindex" => reindex::reindex().await,
+        "reset-db" => reset_db::reset_db(&args[1..]).await,
+        "verify-db" => verify_db::verify_db().await,
+        command => Err(format!("unknown command: {}", command)),
+    };
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            write_log(&format!("{} command problem: {}", args[0], e));
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+//benchmarks don't need the database
+pub fn is_benchmark(args: &[String]) -> bool {
+    args.first()
+        .is_some_and(|command| command == "bench-verify")
+}
+
+//<file> [--from N] [--to N]
+fn chain_file_args(args: &[String]) -> Result<(String, Option<i64>, Option<i64>), String> {
+    let path = match args.first() {
+        Some(path) if !path.starts_with("--") => path.clone(),
+        _ => return Err("path of chain file is needed".to_string()),
+    };
+    let (mut from, mut to) = (None, None);
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let value = match rest.next().map(|value| value.parse::<i64>()) {
+            Some(Ok(value)) => value,
+            _ => return Err(format!("{} needs a block number", arg)),
+        };
+        match arg.as_str() {
+            "--from" => from = Some(value),
+            "--to" => to = Some(value),
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    Ok((path, from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_chain_file_args() {
+        assert_eq!(
+            chain_file_args(&args(&["chain.bin"])),
+            Ok(("chain.bin".to_string(), None, None))
+        );
+        assert_eq!(
+            chain_file_args(&args(&["chain.bin", "--to", "20", "--from", "5"])),
+            Ok(("chain.bin".to_string(), Some(5), Some(20)))
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_chain_file_args() {
+        assert!(chain_file_args(&args(&[])).is_err());
+        assert!(chain_file_args(&args(&["--from", "5"])).is_err());
+        assert!(chain_file_args(&args(&["chain.bin", "--from"])).is_err());
+        assert!(chain_file_args(&args(&["chain.bin", "--from", "five"])).is_err());
+        assert!(chain_file_args(&args(&["chain.bin", "--at", "5"])).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use futures::StreamExt;
+use mongodb::{
+    bson::{doc, from_document, Document},
+    options::FindOptions,
+    Collection,
+};
+
+use crate::handlers::{
+    block_apply::apply_block,
+    chain_spec::{CHAIN_SPEC, GENESIS_PREVHASH},
+    create_log::write_log,
+    db_connection::blockchain_db,
+    migrations::clear_reindex_mark,
+    recieved_block::{check_block_fully, create_hash, verify_block_crypto},
+    structures::Block,
+};
+
+//rebuild UTXOs and reciepts from scratch by replaying all of the blocks in order
+//blocks that fail validation are replayed too (they are in our chain) and reported at the end
+pub async fn reindex() -> Result<(), String> {
+    let db = blockchain_db().await?;
+    let blocks_coll: Collection<Document> = db.collection("Blocks");
+    let utxos_coll: Collection<Document> = db.collection("UTXOs");
+    let reciept_coll: Collection<Document> = db.collection("reciept");
+    let headers_coll: Collection<Document> = db.collection("Headers");
+
+    //bodies of pruned blocks are removed, so UTXOs can't be made again
+    match headers_coll.find_one(None, None).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return Err("database is pruned, resync it instead of reindex".to_string()),
+        Err(e) => return Err(e.to_string()),
+    }
+
+    for coll in [&utxos_coll, &reciept_coll] {
+        if let Err(e) = coll.delete_many(doc! {}, None).await {
+            return Err(format!("removing {} problem: {}", coll.name(), e));
+        }
+    }
+    println!("UTXOs and reciepts removed, replaying blocks");
+
+    let option = FindOptions::builder()
+        .sort(doc! {"header.number": 1})
+        .build();
+    let mut blocks = blocks_coll
+        .find(None, option)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut prev_hash = GENESIS_PREVHASH.to_string();
+    let mut replayed = 0;
+    let mut failed: Vec<(i64, String)> = Vec::new();
+    while let Some(doc) = blocks.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        let block: Block = match from_document(doc) {
+            Ok(block) => block,
+            Err(e) => {
+                return Err(format!(
+                    "block document problem after block {}: {}",
+                    replayed, e
+                ))
+            }
+        };
+        let number = block.header.number;
+
+        if let Err(e) = check_block(&block, &prev_hash, &utxos_coll).await {
+            println!(
+                "block {} ({}) validation problem: {}",
+                number, block.header.blockhash, e
+            );
+            failed.push((number, e));
+        }
+        prev_hash = block.header.blockhash.clone();
+        apply_block(&db, &block)
+            .await
+            .map_err(|e| format!("applying block {} problem: {}", number, e))?;
+
+        replayed += 1;
+        if replayed % 1000 == 0 {
+            println!("{} blocks replayed", replayed);
+        }
+    }
+
+    //UTXOs and reciepts are made from all of the blocks again
+    clear_reindex_mark(&db).await?;
+
+    println!(
+        "reindex completed: {} blocks replayed, {} blocks failed validation",
+        replayed,
+        failed.len()
+    );
+    write_log(&format!(
+        "reindex completed: {} blocks replayed, {} blocks failed validation",
+        replayed,
+        failed.len()
+    ));
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        let numbers: Vec<String> = failed.iter().map(|(n, _)| n.to_string()).collect();
+        Err(format!("failed blocks: {}", numbers.join(", ")))
+    }
+}
+
+//same rules as syncing: chain linkage, body hash, signature and transactions with UTXOs
+async fn check_block(
+    block: &Block,
+    prev_hash: &str,
+    utxos_coll: &Collection<Document>,
+) -> Result<(), String> {
+    if block.header.prevhash != prev_hash {
+        return Err("block is not linked to previous block".to_string());
+    }
+    if block.header.prevhash == GENESIS_PREVHASH && !CHAIN_SPEC.is_genesis(&block.header.blockhash)
+    {
+        return Err("genesis is not the genesis of chain spec".to_string());
+    }
+    let str_block_body = serde_json::to_string(&block.body).map_err(|e| e.to_string())?;
+    if create_hash(str_block_body) != block.header.blockhash {
+        return Err("block hash problem".to_string());
+    }
+    verify_block_crypto(block)
+        .await
+        .map_err(|e| e.to_string())?;
+    check_block_fully(block, utxos_coll)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,31 @@
+use mongodb::{
+    bson::{doc, Document},
+    Collection,
+};
+
+use crate::handlers::{
+    create_log::write_log, db_connection::blockchain_db, migrations::clear_reindex_mark,
+};
+
+//remove all blocks, headers of pruned blocks, UTXOs and reciepts of the relay
+//(schema version stays, an empty database has the layout of this relay)
+//this is the only way to reset the database, blocks from network never remove anything
+pub async fn reset_db(args: &[String]) -> Result<(), String> {
+    if !args.iter().any(|arg| arg == "--yes") {
+        return Err(
+            "reset-db removes all blocks, headers, UTXOs and reciepts, run it with --yes to confirm"
+                .to_string(),
+        );
+    }
+    let db = blockchain_db().await?;
+    for name in ["Blocks", "Headers", "UTXOs", "reciept"] {
+        let coll: Collection<Document> = db.collection(name);
+        match coll.delete_many(doc! {}, None).await {
+            Ok(result) => println!("{}: {} documents removed", name, result.deleted_count),
+            Err(e) => return Err(format!("removing {} problem: {}", name, e)),
+        }
+    }
+    clear_reindex_mark(&db).await?;
+    write_log("database reset with reset-db command");
+    Ok(())
+}
@@ -0,0 +1,393 @@
+use std::collections::{HashMap, HashSet};
+
+use futures::StreamExt;
+use mongodb::{
+    bson::{doc, from_document, Document},
+    options::FindOptions,
+    Collection,
+};
+use serde::Serialize;
+
+use crate::handlers::{
+    chain_spec::{CHAIN_SPEC, GENESIS_PREVHASH},
+    db_connection::blockchain_db,
+    recieved_block::{check_block_sign, create_hash},
+    structures::{Block, BlockHeader, Reciept, UtxoRecord},
+};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Problem {
+    BrokenLink,
+    WrongGenesis,
+    WrongBlockHash,
+    WrongSignature,
+    MissingSigner,
+    MissingInput,
+    MissingUtxo,
+    ExtraUtxo,
+    WrongUtxo,
+    RecieptWithoutBlock,
+    WrongReciept,
+    WrongDocument,
+}
+
+#[derive(Debug, Serialize)]
+struct Discrepancy {
+    problem: Problem,
+    block_number: Option<i64>,
+    //block hash, output hash or transaction hash
+    hash: String,
+    detail: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    network_id: String,
+    blocks: u64,
+    tip: Option<i64>,
+    //number of the first block with body in a pruned database
+    //(UTXOs and reciepts of a pruned database can't be checked)
+    pruned_below: Option<i64>,
+    utxos: u64,
+    confirmed_reciepts: u64,
+    //unspent outputs of blocks that are not in UTXOs because their owners have pending
+    //transactions in the mempool (they are not discrepancies)
+    spent_by_pending: u64,
+    consistent: bool,
+    discrepancies: Vec<Discrepancy>,
+}
+
+//check the local database without changing it and print a json report of the discrepancies
+//(reindex fixes UTXOs and reciepts, wrong blocks need resyncing)
+pub async fn verify_db() -> Result<(), String> {
+    let db = blockchain_db().await?;
+    let blocks_coll: Collection<Document> = db.collection("Blocks");
+    let utxos_coll: Collection<Document> = db.collection("UTXOs");
+    let reciept_coll: Collection<Document> = db.collection("reciept");
+    let mut report = Report {
+        network_id: CHAIN_SPEC.network_id.clone(),
+        blocks: 0,
+        tip: None,
+        pruned_below: None,
+        utxos: 0,
+        confirmed_reciepts: 0,
+        spent_by_pending: 0,
+        consistent: true,
+        discrepancies: Vec::new(),
+    };
+
+    //walk blocks from genesis and derive UTXOs and the block of every transaction
+    let mut derived: HashMap<String, UtxoRecord> = HashMap::new();
+    let mut tx_blocks: HashMap<String, i64> = HashMap::new();
+    let mut prev_hash = GENESIS_PREVHASH.to_string();
+
+    //headers of pruned blocks are only checked for linkage
+    let headers_coll: Collection<Document> = db.collection("Headers");
+    let option = FindOptions::builder()
+        .sort(doc! {"header.number": 1})
+        .build();
+    let mut headers = headers_coll
+        .find(None, option)
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(doc) = headers.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        let header: BlockHeader = match doc
+            .get_document("header")
+            .map(|header| from_document(header.clone()))
+        {
+            Ok(Ok(header)) => header,
+            _ => {
+                report.discrepancies.push(Discrepancy {
+                    problem: Problem::WrongDocument,
+                    block_number: report.tip.map(|tip| tip + 1),
+                    hash: String::new(),
+                    detail: "Headers: header document problem".to_string(),
+                });
+                continue;
+            }
+        };
+        if header.prevhash != prev_hash {
+            report.discrepancies.push(Discrepancy {
+                problem: Problem::BrokenLink,
+                block_number: Some(header.number),
+                hash: header.blockhash.clone(),
+                detail: format!("prevhash {} is not {}", header.prevhash, prev_hash),
+            });
+        }
+        prev_hash = header.blockhash;
+        report.blocks += 1;
+        report.tip = Some(header.number);
+    }
+    let pruned = report.tip.is_some();
+
+    let option = FindOptions::builder()
+        .sort(doc! {"header.number": 1})
+        .build();
+    let mut blocks = blocks_coll
+        .find(None, option)
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(doc) = blocks.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        let block: Block = match from_document(doc) {
+            Ok(block) => block,
+            Err(e) => {
+                report.discrepancies.push(Discrepancy {
+                    problem: Problem::WrongDocument,
+                    block_number: report.tip.map(|tip| tip + 1),
+                    hash: String::new(),
+                    detail: e.to_string(),
+                });
+                continue;
+            }
+        };
+        check_block(&block, &prev_hash, &mut report.discrepancies);
+        if pruned {
+            //outputs of pruned blocks are unknown, so UTXOs can't be derived
+            report.pruned_below = report.pruned_below.or(Some(block.header.number));
+        } else {
+            derive_block(
+                &block,
+                &mut derived,
+                &mut tx_blocks,
+                &mut report.discrepancies,
+            );
+        }
+        prev_hash = block.header.blockhash;
+        report.blocks += 1;
+        report.tip = Some(block.header.number);
+    }
+
+    if pruned {
+        return finish_report(report);
+    }
+
+    //live UTXOs must be equal to the derived set
+    let mut utxos = utxos_coll
+        .find(None, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(doc) = utxos.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        report.utxos += 1;
+        let record: UtxoRecord = match from_document(doc) {
+            Ok(record) => record,
+            Err(e) => {
+                report.discrepancies.push(Discrepancy {
+                    problem: Problem::WrongDocument,
+                    block_number: None,
+                    hash: String::new(),
+                    detail: format!("UTXOs: {}", e),
+                });
+                continue;
+            }
+        };
+        match derived.remove(&record.output_hash) {
+            Some(expected) if expected == record => {}
+            Some(expected) => report.discrepancies.push(Discrepancy {
+                problem: Problem::WrongUtxo,
+                block_number: Some(record.block_number),
+                hash: record.output_hash,
+                detail: format!(
+                    "expected {} for {} from block {}",
+                    expected.unspent, expected.public_key, expected.block_number
+                ),
+            }),
+            None => report.discrepancies.push(Discrepancy {
+                problem: Problem::ExtraUtxo,
+                block_number: Some(record.block_number),
+                hash: record.output_hash,
+                detail: "output is not unspent in blocks".to_string(),
+            }),
+        }
+    }
+    //unspent outputs of blocks that are not in UTXOs
+    //(pending transactions of mempool spend outputs of their signers before they are in a block)
+    let pending_signers = pending_signers(&reciept_coll).await?;
+    for (output_hash, record) in derived {
+        if pending_signers.contains(&record.public_key) {
+            report.spent_by_pending += 1;
+            continue;
+        }
+        report.discrepancies.push(Discrepancy {
+            problem: Problem::MissingUtxo,
+            block_number: Some(record.block_number),
+            hash: output_hash,
+            detail: format!(
+                "{} of {} is not in UTXOs",
+                record.unspent, record.public_key
+            ),
+        });
+    }
+
+    //confirmed reciepts must point at the block that contains their transaction
+    let mut reciepts = reciept_coll
+        .find(doc! {"status": "Confirmed"}, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(doc) = reciepts.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        report.confirmed_reciepts += 1;
+        let reciept: Reciept = match from_document(doc) {
+            Ok(reciept) => reciept,
+            Err(e) => {
+                report.discrepancies.push(Discrepancy {
+                    problem: Problem::WrongDocument,
+                    block_number: None,
+                    hash: String::new(),
+                    detail: format!("reciept: {}", e),
+                });
+                continue;
+            }
+        };
+        match tx_blocks.get(&reciept.hash) {
+            Some(number) if reciept.block_number == Some(*number) => {}
+            Some(number) => report.discrepancies.push(Discrepancy {
+                problem: Problem::WrongReciept,
+                block_number: reciept.block_number,
+                hash: reciept.hash,
+                detail: format!("transaction is in block {}", number),
+            }),
+            None => report.discrepancies.push(Discrepancy {
+                problem: Problem::RecieptWithoutBlock,
+                block_number: reciept.block_number,
+                hash: reciept.hash,
+                detail: "transaction is not in any block".to_string(),
+            }),
+        }
+    }
+
+    finish_report(report)
+}
+
+//signers of the pending transactions of mempool
+async fn pending_signers(reciept_coll: &Collection<Document>) -> Result<HashSet<String>, String> {
+    let mut signers = HashSet::new();
+    let mut pending = reciept_coll
+        .find(doc! {"status": "pending"}, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(doc) = pending.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        if let Ok(reciept) = from_document::<Reciept>(doc) {
+            signers.insert(reciept.from);
+        }
+    }
+    Ok(signers)
+}
+
+fn finish_report(mut report: Report) -> Result<(), String> {
+    report.consistent = report.discrepancies.is_empty();
+    let str_report = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    println!("{}", str_report);
+    if report.consistent {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} discrepancies in database",
+            report.discrepancies.len()
+        ))
+    }
+}
+
+//check linkage, hash and signature of a block
+fn check_block(block: &Block, prev_hash: &str, discrepancies: &mut Vec<Discrepancy>) {
+    let mut problem = |problem: Problem, detail: &str| {
+        discrepancies.push(Discrepancy {
+            problem,
+            block_number: Some(block.header.number),
+            hash: block.header.blockhash.clone(),
+            detail: detail.to_string(),
+        })
+    };
+    if block.header.prevhash != prev_hash {
+        problem(
+            Problem::BrokenLink,
+            &format!("prevhash {} is not {}", block.header.prevhash, prev_hash),
+        );
+    }
+    if block.header.prevhash == GENESIS_PREVHASH && !CHAIN_SPEC.is_genesis(&block.header.blockhash)
+    {
+        problem(
+            Problem::WrongGenesis,
+            "genesis is not the genesis of chain spec",
+        );
+    }
+    let str_block_body = serde_json::to_string(&block.body).unwrap();
+    if create_hash(str_block_body) != block.header.blockhash {
+        problem(
+            Problem::WrongBlockHash,
+            "hash of body is not the block hash",
+        );
+    }
+    if let Err(e) = check_block_sign(block) {
+        problem(Problem::WrongSignature, e);
+    }
+}
+
+//apply a block to the derived UTXOs with the same rules as BlockChanges (block_apply.rs)
+fn derive_block(
+    block: &Block,
+    derived: &mut HashMap<String, UtxoRecord>,
+    tx_blocks: &mut HashMap<String, i64>,
+    discrepancies: &mut Vec<Discrepancy>,
+) {
+    let number = block.header.number;
+    let coinbase = &block.body.coinbase;
+    tx_blocks.insert(coinbase.tx_hash.clone(), number);
+    for output in coinbase.output.utxos.iter() {
+        derived.insert(
+            output.hash.clone(),
+            UtxoRecord {
+                output_hash: output.hash.clone(),
+                public_key: output.output_unspent.public_key.clone(),
+                transaction_hash: coinbase.tx_hash.clone(),
+                unspent: output.output_unspent.unspent.round_dp(12),
+                block_number: number,
+            },
+        );
+    }
+
+    for tx in block.body.transactions.iter() {
+        tx_blocks.insert(tx.tx_hash.clone(), number);
+        let signer = match tx.output.output_data.sigenr_public_keys.first() {
+            Some(signer) => signer.to_string(),
+            None => {
+                discrepancies.push(Discrepancy {
+                    problem: Problem::MissingSigner,
+                    block_number: Some(number),
+                    hash: tx.tx_hash.clone(),
+                    detail: "transaction doesn't have a signer public key".to_string(),
+                });
+                continue;
+            }
+        };
+        for input in tx.input.input_data.utxos.iter() {
+            match derived.get(&input.output_hash) {
+                Some(record) if record.public_key == signer => {
+                    derived.remove(&input.output_hash);
+                }
+                _ => discrepancies.push(Discrepancy {
+                    problem: Problem::MissingInput,
+                    block_number: Some(number),
+                    hash: input.output_hash.clone(),
+                    detail: format!("input of transaction {} is not unspent", tx.tx_hash),
+                }),
+            }
+        }
+        for output in tx.output.output_data.utxos.iter() {
+            derived.insert(
+                output.hash.clone(),
+                UtxoRecord {
+                    output_hash: output.hash.clone(),
+                    public_key: output.output_unspent.public_key.clone(),
+                    transaction_hash: tx.tx_hash.clone(),
+                    unspent: output.output_unspent.unspent.round_dp(12),
+                    block_number: number,
+                },
+            );
+        }
+    }
+}
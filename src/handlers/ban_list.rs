@@ -0,0 +1,434 @@
+// Banned peers and addresses
+//
+// Bans are kept in bans.json, so a restarted relay doesn't accept peers that it has banned before,
+// and the same file can be exported from one relay and imported in others. A ban is for a peer id
+// or for an IP address or network (CIDR) and it ends at its expiry time or when it is removed.
+// The ban_guard behaviour of the swarm denies dialing and accepting connections of banned peers and
+// addresses; connections that are open when a ban is added are closed by the swarm loop.
+
+use std::{
+    collections::HashMap,
+    env::consts::OS,
+    fmt, fs,
+    net::IpAddr,
+    str::FromStr,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use chrono::Utc;
+use ipnet::IpNet;
+use libp2p::{
+    core::{multiaddr::Protocol, Endpoint},
+    swarm::{
+        dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId, Swarm,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use super::{create_log::write_log, swarm_config::CustomBehav};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BanTarget {
+    Peer(PeerId),
+    //one address is a network with all of the bits (/32 or /128)
+    Ip(IpNet),
+}
+
+impl FromStr for BanTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(peer_id) = s.parse::<PeerId>() {
+            Ok(BanTarget::Peer(peer_id))
+        } else if let Ok(net) = s.parse::<IpNet>() {
+            Ok(BanTarget::Ip(net.trunc()))
+        } else if let Ok(ip) = s.parse::<IpAddr>() {
+            Ok(BanTarget::Ip(IpNet::from(ip)))
+        } else {
+            Err(format!("{} is not a peer id, IP address or CIDR", s))
+        }
+    }
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanTarget::Peer(peer_id) => write!(f, "{}", peer_id),
+            BanTarget::Ip(net) => write!(f, "{}", net),
+        }
+    }
+}
+
+impl BanTarget {
+    fn matches(&self, peer_id: Option<&PeerId>, ip: Option<IpAddr>) -> bool {
+        match self {
+            BanTarget::Peer(banned) => peer_id == Some(banned),
+            BanTarget::Ip(net) => ip.is_some_and(|ip| net.contains(&ip)),
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Ban {
+    #[serde_as(as = "DisplayFromStr")]
+    #[serde(alias = "peer_id")]
+    pub target: BanTarget,
+    //unix time (seconds) of the end of the ban, none: until it is removed
+    #[serde(default)]
+    pub until: Option<i64>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl Ban {
+    fn is_active(&self, now: i64) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+}
+
+pub static BAN_LIST: Lazy<Mutex<Vec<Ban>>> = Lazy::new(|| Mutex::new(load_bans()));
+
+fn bans_path() -> &'static str {
+    if OS == "windows" {
+        "bans.json"
+    } else {
+        "/etc/bans.json"
+    }
+}
+
+fn load_bans() -> Vec<Ban> {
+    match fs::read_to_string(bans_path()) {
+        Ok(str_bans) => match serde_json::from_str::<Vec<Ban>>(&str_bans) {
+            Ok(bans) => bans,
+            Err(e) => {
+                write_log(&format!("ban list problem, it is ignored: {}", e));
+                Vec::new()
+            }
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+fn save_bans(bans: &[Ban]) {
+    match serde_json::to_string_pretty(bans) {
+        Ok(str_bans) => {
+            if let Err(e) = fs::write(bans_path(), str_bans) {
+                write_log(&format!("saving ban list problem: {}", e));
+            }
+        }
+        Err(e) => write_log(&format!("saving ban list problem: {}", e)),
+    }
+}
+
+//unix time after minutes (none: no expiry)
+pub fn expiry(minutes: Option<u64>) -> Option<i64> {
+    minutes.map(|minutes| Utc::now().timestamp() + (minutes * 60) as i64)
+}
+
+//add a ban or replace the ban of the same target
+pub fn add_ban(ban: Ban) {
+    let mut bans = BAN_LIST.lock().unwrap();
+    write_log(&format!("{} banned: {}", ban.target, ban.reason));
+    bans.retain(|old| old.target != ban.target);
+    bans.push(ban);
+    save_bans(&bans);
+}
+
+//ban a peer for minutes (a longer ban of the peer is kept) and close its connections
+pub fn ban_peer(swarm: &mut Swarm<CustomBehav>, peer_id: PeerId, minutes: u64, reason: &str) {
+    let target = BanTarget::Peer(peer_id);
+    let until = expiry(Some(minutes));
+    let longer = BAN_LIST
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|ban| ban.target == target)
+        .map(|ban| ban.until.is_none() || ban.until > until);
+    if longer != Some(true) {
+        add_ban(Ban {
+            target,
+            until,
+            reason: reason.to_string(),
+        });
+    }
+    let _ = swarm.disconnect_peer_id(peer_id);
+}
+
+pub fn remove_ban(target: &BanTarget) -> bool {
+    let mut bans = BAN_LIST.lock().unwrap();
+    let len = bans.len();
+    bans.retain(|ban| &ban.target != target);
+    if bans.len() == len {
+        return false;
+    }
+    save_bans(&bans);
+    write_log(&format!("ban of {} removed", target));
+    true
+}
+
+pub fn set_ban_expiry(target: &BanTarget, until: Option<i64>) -> bool {
+    let mut bans = BAN_LIST.lock().unwrap();
+    match bans.iter_mut().find(|ban| &ban.target == target) {
+        Some(ban) => {
+            ban.until = until;
+            save_bans(&bans);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn bans() -> Vec<Ban> {
+    BAN_LIST.lock().unwrap().clone()
+}
+
+//add bans of another relay, they replace bans of the same targets
+pub fn import_bans(imported: Vec<Ban>) -> usize {
+    let mut bans = BAN_LIST.lock().unwrap();
+    let now = Utc::now().timestamp();
+    let imported: Vec<Ban> = imported
+        .into_iter()
+        .filter(|ban| ban.is_active(now))
+        .collect();
+    bans.retain(|ban| !imported.iter().any(|new| new.target == ban.target));
+    let count = imported.len();
+    bans.extend(imported);
+    save_bans(&bans);
+    write_log(&format!("{} bans imported", count));
+    count
+}
+
+pub fn is_banned(peer_id: Option<&PeerId>, ip: Option<IpAddr>) -> bool {
+    let now = Utc::now().timestamp();
+    BAN_LIST
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|ban| ban.is_active(now) && ban.target.matches(peer_id, ip))
+}
+
+//banned peer id (/p2p) or IP address of a multiaddress
+pub fn is_banned_addr(addr: &Multiaddr) -> bool {
+    let mut peer_id = None;
+    let mut ip = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2p(id) => peer_id = Some(id),
+            Protocol::Ip4(ip4) => ip = Some(IpAddr::V4(ip4)),
+            Protocol::Ip6(ip6) => ip = Some(IpAddr::V6(ip6)),
+            _ => {}
+        }
+    }
+    is_banned(peer_id.as_ref(), ip)
+}
+
+fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip4) => Some(IpAddr::V4(ip4)),
+        Protocol::Ip6(ip6) => Some(IpAddr::V6(ip6)),
+        _ => None,
+    })
+}
+
+//remove expired bans
+pub fn lift_expired_bans() {
+    let now = Utc::now().timestamp();
+    let mut bans = BAN_LIST.lock().unwrap();
+    let len = bans.len();
+    bans.retain(|ban| ban.is_active(now));
+    if bans.len() != len {
+        save_bans(&bans);
+        write_log(&format!("{} bans expired", len - bans.len()));
+    }
+}
+
+//close open connections of peers and addresses that are banned after they were connected
+pub fn close_banned_connections(swarm: &mut Swarm<CustomBehav>) {
+    for connection_id in swarm.behaviour().ban_guard.banned_connections() {
+        swarm.close_connection(connection_id);
+    }
+}
+
+fn denied(target: &str) -> ConnectionDenied {
+    ConnectionDenied::new(format!("{} is banned", target))
+}
+
+//behaviour of the swarm that denies connections of the ban list
+#[derive(Default)]
+pub struct BanGuard {
+    connections: HashMap<ConnectionId, (PeerId, Option<IpAddr>)>,
+}
+
+impl BanGuard {
+    fn banned_connections(&self) -> Vec<ConnectionId> {
+        self.connections
+            .iter()
+            .filter(|(_, (peer_id, ip))| is_banned(Some(peer_id), *ip))
+            .map(|(connection_id, _)| *connection_id)
+            .collect()
+    }
+}
+
+impl NetworkBehaviour for BanGuard {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = ();
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        if is_banned(None, ip_of(remote_addr)) {
+            return Err(denied(&remote_addr.to_string()));
+        }
+        Ok(())
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        if is_banned(Some(&peer), ip_of(remote_addr)) {
+            return Err(denied(&peer.to_string()));
+        }
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if let Some(peer) = maybe_peer {
+            if is_banned(Some(&peer), None) {
+                return Err(denied(&peer.to_string()));
+            }
+        }
+        if let Some(addr) = addresses.iter().find(|addr| is_banned_addr(addr)) {
+            return Err(denied(&addr.to_string()));
+        }
+        Ok(vec![])
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        if is_banned(Some(&peer), ip_of(addr)) {
+            return Err(denied(&peer.to_string()));
+        }
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                let ip = ip_of(established.endpoint.get_remote_address());
+                self.connections
+                    .insert(established.connection_id, (established.peer_id, ip));
+            }
+            FromSwarm::ConnectionClosed(closed) => {
+                self.connections.remove(&closed.connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, _cx: &mut Context<'_>) -> Poll<ToSwarm<(), THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_targets() {
+        let peer_id = PeerId::random();
+        assert_eq!(
+            peer_id.to_string().parse::<BanTarget>(),
+            Ok(BanTarget::Peer(peer_id))
+        );
+        assert_eq!(
+            " 10.0.0.7 ".parse::<BanTarget>(),
+            Ok(BanTarget::Ip("10.0.0.7/32".parse().unwrap()))
+        );
+        //networks are truncated to their prefix
+        assert_eq!(
+            "192.168.1.9/24".parse::<BanTarget>(),
+            Ok(BanTarget::Ip("192.168.1.0/24".parse().unwrap()))
+        );
+        assert_eq!(
+            "::1".parse::<BanTarget>(),
+            Ok(BanTarget::Ip("::1/128".parse().unwrap()))
+        );
+        assert!("not a target".parse::<BanTarget>().is_err());
+    }
+
+    #[test]
+    fn matches_peers_and_addresses() {
+        let peer_id = PeerId::random();
+        let peer = BanTarget::Peer(peer_id);
+        assert!(peer.matches(Some(&peer_id), None));
+        assert!(!peer.matches(Some(&PeerId::random()), None));
+        assert!(!peer.matches(None, Some("10.0.0.7".parse().unwrap())));
+
+        let net: BanTarget = "10.0.0.0/8".parse().unwrap();
+        assert!(net.matches(None, Some("10.1.2.3".parse().unwrap())));
+        assert!(net.matches(Some(&peer_id), Some("10.1.2.3".parse().unwrap())));
+        assert!(!net.matches(None, Some("11.1.2.3".parse().unwrap())));
+        assert!(!net.matches(Some(&peer_id), None));
+    }
+
+    #[test]
+    fn bans_expire() {
+        let ban = Ban {
+            target: BanTarget::Peer(PeerId::random()),
+            until: Some(100),
+            reason: String::new(),
+        };
+        assert!(ban.is_active(99));
+        assert!(!ban.is_active(100));
+        let ban = Ban { until: None, ..ban };
+        assert!(ban.is_active(i64::MAX));
+    }
+
+    #[test]
+    fn reads_old_bans_with_peer_id() {
+        let peer_id = PeerId::random();
+        let str_bans = format!(
+            r#"[{{"peer_id":"{}"}}, {{"target":"10.0.0.0/8"}}]"#,
+            peer_id
+        );
+        let bans: Vec<Ban> = serde_json::from_str(&str_bans).unwrap();
+        assert_eq!(bans[0].target, BanTarget::Peer(peer_id));
+        assert_eq!(bans[0].until, None);
+        assert_eq!(bans[1].target, "10.0.0.0/8".parse().unwrap());
+    }
+}
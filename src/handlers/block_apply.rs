@@ -0,0 +1,349 @@
+// Applying a block to UTXOs and reciepts
+//
+// All of the changes of a block are made in memory first (BlockChanges), then they are written
+// with a few bulk writes: one delete for spent outputs, one update command with upserts for new
+// outputs and transaction reciepts and one insert for coinbase reciepts. Writes are idempotent, so
+// applying a block again (reindex, a crash in the middle) doesn't change the result.
+
+use std::{collections::HashSet, time::Instant};
+
+use mongodb::{
+    bson::{doc, to_document, Document},
+    Collection, Database,
+};
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
+
+use super::{
+    metrics::record_block_apply,
+    reciept::{coinbase_reciepts, tx_reciept},
+    structures::{Block, Reciept, UtxoRecord},
+};
+
+//max number of writes in one command
+const BULK_BATCH: usize = 1000;
+
+//blocks are inserted and applied while holding it, so readers that need a fully applied tip
+//(snapshots) wait for the block in work
+pub static BLOCK_WRITES: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, Default)]
+pub struct BlockChanges {
+    //(output hash, owner) of spent outputs
+    pub spent: Vec<(String, String)>,
+    pub utxos: Vec<UtxoRecord>,
+    //reciepts of transactions replace their pending reciepts
+    pub reciepts: Vec<Reciept>,
+    pub coinbase_reciepts: Vec<Reciept>,
+}
+
+impl BlockChanges {
+    //a transaction without a signer is an error
+    pub fn from_block(block: &Block) -> Result<Self, String> {
+        let number = block.header.number;
+        let coinbase = &block.body.coinbase;
+        let mut changes = BlockChanges {
+            coinbase_reciepts: coinbase_reciepts(
+                coinbase,
+                Some(number),
+                "Confirmed".to_string(),
+                "Coinbase".to_string(),
+                &block.header,
+            ),
+            ..Default::default()
+        };
+        for output in coinbase.output.utxos.iter() {
+            changes.utxos.push(UtxoRecord {
+                output_hash: output.hash.clone(),
+                public_key: output.output_unspent.public_key.clone(),
+                transaction_hash: coinbase.tx_hash.clone(),
+                unspent: output.output_unspent.unspent.round_dp(12),
+                block_number: number,
+            });
+        }
+
+        for tx in block.body.transactions.iter() {
+            let signer = match tx.output.output_data.sigenr_public_keys.first() {
+                Some(signer) => signer.to_string(),
+                None => return Err(format!("transaction {} has no signer", tx.tx_hash)),
+            };
+            for input in tx.input.input_data.utxos.iter() {
+                changes
+                    .spent
+                    .push((input.output_hash.clone(), signer.clone()));
+            }
+            for output in tx.output.output_data.utxos.iter() {
+                changes.utxos.push(UtxoRecord {
+                    output_hash: output.hash.clone(),
+                    public_key: output.output_unspent.public_key.clone(),
+                    transaction_hash: tx.tx_hash.clone(),
+                    unspent: output.output_unspent.unspent.round_dp(12),
+                    block_number: number,
+                });
+            }
+            changes.reciepts.push(tx_reciept(
+                tx,
+                Some(number),
+                "Confirmed".to_string(),
+                "".to_string(),
+            ));
+        }
+
+        //outputs that are spent in the same block never reach UTXOs
+        let spent: HashSet<(String, String)> = changes.spent.iter().cloned().collect();
+        let created: HashSet<(String, String)> = changes
+            .utxos
+            .iter()
+            .map(|record| (record.output_hash.clone(), record.public_key.clone()))
+            .collect();
+        changes.utxos.retain(|record| {
+            !spent.contains(&(record.output_hash.clone(), record.public_key.clone()))
+        });
+        changes.spent.retain(|outpoint| !created.contains(outpoint));
+        Ok(changes)
+    }
+}
+
+//write changes of a block to UTXOs and reciepts and record the time of it
+pub async fn apply_block(db: &Database, block: &Block) -> Result<(), String> {
+    let start = Instant::now();
+    let changes = BlockChanges::from_block(block)?;
+    let utxos_coll: Collection<Document> = db.collection("UTXOs");
+    let reciept_coll: Collection<Document> = db.collection("reciept");
+
+    for spent in changes.spent.chunks(BULK_BATCH) {
+        let outpoints: Vec<Document> = spent
+            .iter()
+            .map(|(output_hash, public_key)| doc! {"_id": output_hash, "public_key": public_key})
+            .collect();
+        utxos_coll
+            .delete_many(doc! {"$or": outpoints}, None)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut utxo_updates = Vec::new();
+    for record in changes.utxos.iter() {
+        let record_doc = to_document(record).map_err(|e| e.to_string())?;
+        utxo_updates
+            .push(doc! {"q": {"_id": &record.output_hash}, "u": record_doc, "upsert": true});
+    }
+    bulk_update(db, "UTXOs", utxo_updates).await?;
+
+    let mut reciept_updates = Vec::new();
+    for reciept in changes.reciepts.iter() {
+        let reciept_doc = to_document(reciept).map_err(|e| e.to_string())?;
+        reciept_updates.push(doc! {"q": {"hash": &reciept.hash}, "u": reciept_doc, "upsert": true});
+    }
+    bulk_update(db, "reciept", reciept_updates).await?;
+
+    //coinbase has one reciept for each output, they are replaced together
+    if !changes.coinbase_reciepts.is_empty() {
+        let coinbase_hash = &block.body.coinbase.tx_hash;
+        let mut coinbase_docs = Vec::new();
+        for reciept in changes.coinbase_reciepts.iter() {
+            coinbase_docs.push(to_document(reciept).map_err(|e| e.to_string())?);
+        }
+        reciept_coll
+            .delete_many(doc! {"hash": coinbase_hash, "from": "Coinbase"}, None)
+            .await
+            .map_err(|e| e.to_string())?;
+        reciept_coll
+            .insert_many(coinbase_docs, None)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    record_block_apply(block.header.number, start.elapsed());
+    Ok(())
+}
+
+//update command with many statements ({q, u, upsert}) in batches
+async fn bulk_update(db: &Database, coll: &str, updates: Vec<Document>) -> Result<(), String> {
+    for batch in updates.chunks(BULK_BATCH) {
+        let command = doc! {"update": coll, "updates": batch.to_vec(), "ordered": false};
+        let result = db
+            .run_command(command, None)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Ok(errors) = result.get_array("writeErrors") {
+            if !errors.is_empty() {
+                return Err(format!("{} write errors in {}", errors.len(), coll));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use sp_core::ecdsa::Public;
+
+    use super::*;
+    use crate::handlers::structures::{
+        BlockHeader, BlockSign, Body, CoinbaseData, CoinbaseOutput, CoinbaseTransaction, InputData,
+        OutputData, OutputUnspent, OutputUtxo, Transaction, TransactionScript, TxInput, TxOutput,
+        UtxoData,
+    };
+
+    fn output(hash: &str, owner: &str, unspent: i64) -> OutputUtxo {
+        OutputUtxo {
+            hash: hash.to_string(),
+            output_unspent: OutputUnspent {
+                public_key: owner.to_string(),
+                unspent: Decimal::from(unspent),
+                rnum: 0,
+            },
+        }
+    }
+
+    fn transaction(
+        hash: &str,
+        signer: Public,
+        inputs: &[&str],
+        outputs: Vec<OutputUtxo>,
+    ) -> Transaction {
+        Transaction {
+            tx_hash: hash.to_string(),
+            input: TxInput {
+                input_hash: String::new(),
+                input_data: InputData {
+                    number: inputs.len() as u32,
+                    utxos: inputs
+                        .iter()
+                        .map(|input| UtxoData {
+                            transaction_hash: String::new(),
+                            unspent: Decimal::ONE,
+                            output_hash: input.to_string(),
+                            block_number: 1,
+                        })
+                        .collect(),
+                    script: TransactionScript::SingleSig,
+                },
+                signatures: Vec::new(),
+            },
+            output: TxOutput {
+                output_hash: String::new(),
+                output_data: OutputData {
+                    number: outputs.len() as u32,
+                    utxos: outputs,
+                    sigenr_public_keys: vec![signer],
+                },
+            },
+            value: Decimal::ONE,
+            fee: Decimal::ZERO,
+            date: String::new(),
+        }
+    }
+
+    fn block(number: i64, coinbase: Vec<OutputUtxo>, transactions: Vec<Transaction>) -> Block {
+        Block {
+            header: BlockHeader {
+                blockhash: format!("block{}", number),
+                prevhash: String::new(),
+                number,
+                validator: String::new(),
+                validator_blocks_number: 0,
+                merkel_root: String::new(),
+                block_signature: BlockSign {
+                    wallet_public: Public::from_raw([2; 33]),
+                    signature: Vec::new(),
+                    peer_public: Vec::new(),
+                },
+                date: String::new(),
+            },
+            body: Body {
+                coinbase: CoinbaseTransaction {
+                    tx_hash: "coinbase".to_string(),
+                    coinbase_data: CoinbaseData {
+                        block_len: transactions.len(),
+                        merkel_root: String::new(),
+                        reward: Decimal::ZERO,
+                    },
+                    output: CoinbaseOutput {
+                        number: coinbase.len() as u32,
+                        utxos: coinbase,
+                    },
+                    value: Decimal::ZERO,
+                },
+                transactions,
+            },
+        }
+    }
+
+    #[test]
+    fn changes_of_a_block() {
+        let signer = Public::from_raw([3; 33]);
+        let owner = signer.to_string();
+        let tx = transaction(
+            "tx1",
+            signer,
+            &["old1", "old2"],
+            vec![output("new1", "bob", 1), output("change1", &owner, 1)],
+        );
+        let block = block(7, vec![output("reward1", "validator", 5)], vec![tx]);
+        let changes = BlockChanges::from_block(&block).unwrap();
+
+        assert_eq!(
+            changes.spent,
+            vec![
+                ("old1".to_string(), owner.clone()),
+                ("old2".to_string(), owner.clone())
+            ]
+        );
+        let utxos: Vec<(&str, &str, &str, i64)> = changes
+            .utxos
+            .iter()
+            .map(|record| {
+                (
+                    record.output_hash.as_str(),
+                    record.public_key.as_str(),
+                    record.transaction_hash.as_str(),
+                    record.block_number,
+                )
+            })
+            .collect();
+        assert_eq!(
+            utxos,
+            vec![
+                ("reward1", "validator", "coinbase", 7),
+                ("new1", "bob", "tx1", 7),
+                ("change1", owner.as_str(), "tx1", 7),
+            ]
+        );
+        assert_eq!(changes.reciepts.len(), 1);
+        assert_eq!(changes.reciepts[0].from, owner);
+        assert_eq!(changes.reciepts[0].to, "bob");
+        assert_eq!(changes.reciepts[0].block_number, Some(7));
+        assert_eq!(changes.coinbase_reciepts.len(), 1);
+        assert_eq!(changes.coinbase_reciepts[0].to, "validator");
+    }
+
+    #[test]
+    fn outputs_spent_in_the_same_block() {
+        let alice = Public::from_raw([3; 33]);
+        let bob = Public::from_raw([4; 33]);
+        let first = transaction(
+            "tx1",
+            alice,
+            &["old1"],
+            vec![output("mid1", &bob.to_string(), 1)],
+        );
+        let second = transaction("tx2", bob, &["mid1"], vec![output("new1", "carol", 1)]);
+        let changes = BlockChanges::from_block(&block(3, Vec::new(), vec![first, second])).unwrap();
+
+        assert_eq!(changes.spent, vec![("old1".to_string(), alice.to_string())]);
+        assert_eq!(changes.utxos.len(), 1);
+        assert_eq!(changes.utxos[0].output_hash, "new1");
+        assert_eq!(changes.reciepts.len(), 2);
+    }
+
+    #[test]
+    fn transaction_without_a_signer() {
+        let mut tx = transaction("tx1", Public::from_raw([3; 33]), &["old1"], Vec::new());
+        tx.output.output_data.sigenr_public_keys.clear();
+
+        assert!(BlockChanges::from_block(&block(4, Vec::new(), vec![tx])).is_err());
+    }
+}
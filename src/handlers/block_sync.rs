@@ -0,0 +1,559 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use libp2p::{futures::StreamExt, request_response::OutboundRequestId, PeerId, Swarm};
+use mongodb::{
+    bson::{doc, from_document, Document},
+    options::{FindOneOptions, FindOptions},
+    Collection,
+};
+use tokio::sync::mpsc;
+
+use super::{
+    chain_spec::{CHAIN_SPEC, GENESIS_PREVHASH},
+    config::{SyncMode, CONFIG},
+    create_log::write_log,
+    db_connection::blockchain_db,
+    pruning::pruned_headers,
+    recieved_block::{check_block_sign, create_hash, insert_synced_block},
+    requests::Handshake,
+    structures::{Block, BlockHeader, BlocksReq, HeadersReq, Req, Res},
+    CustomBehav,
+};
+
+//max number of headers in one response
+pub const HEADERS_BATCH: i64 = 500;
+//max number of blocks in one response (cbor responses are limited to 10MB)
+pub const BLOCKS_BATCH: i64 = 16;
+//max number of block requests that are waiting for response from one peer
+const MAX_INFLIGHT_PER_PEER: usize = 2;
+//max number of chunks that are requested, arrived or inserting
+//(blocks are downloaded faster than they are validated and inserted)
+const MAX_BUFFERED_CHUNKS: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum SyncProgress {
+    //response was not for block syncing
+    NotMine,
+    Pending,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SyncRequest {
+    //handshake of a peer tells the blocks that it has bodies of
+    Handshake,
+    Headers,
+    //index of first header of the requested chunk
+    Blocks(usize),
+}
+
+//headers-first syncing of the blocks above our tip from connected relays over request-response
+//blocks are validated and inserted in order by an inserter task, out of the swarm loop
+pub struct BlockSync {
+    pub source: Option<PeerId>,
+    peers: Vec<PeerId>,
+    tip_hash: String,
+    tip_number: i64,
+    headers: Vec<BlockHeader>,
+    queue: VecDeque<usize>,
+    //oldest block with body of the peers that have answered the handshake (none: all blocks)
+    bodies_from: HashMap<PeerId, Option<i64>>,
+    pending: HashMap<OutboundRequestId, (PeerId, SyncRequest)>,
+    arrived: BTreeMap<usize, Vec<Block>>,
+    //index of the first header that is not sent to the inserter
+    next_apply: usize,
+    //chunks that are sent to the inserter and number of blocks that it has inserted
+    inserting: usize,
+    inserted: usize,
+    inserter: Option<mpsc::UnboundedSender<Vec<Block>>>,
+    //number of inserted blocks of every chunk or number of the block that could not be inserted
+    inserter_results: Option<mpsc::UnboundedReceiver<Result<usize, i64>>>,
+}
+
+impl BlockSync {
+    pub fn new() -> Self {
+        Self {
+            source: None,
+            peers: Vec::new(),
+            tip_hash: String::new(),
+            tip_number: 0,
+            headers: Vec::new(),
+            queue: VecDeque::new(),
+            bodies_from: HashMap::new(),
+            pending: HashMap::new(),
+            arrived: BTreeMap::new(),
+            next_apply: 0,
+            inserting: 0,
+            inserted: 0,
+            inserter: None,
+            inserter_results: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    //start syncing from our last block (or genesis in full sync mode with an empty database)
+    //returns false if syncing could not start
+    pub async fn start(
+        &mut self,
+        swarm: &mut Swarm<CustomBehav>,
+        source: PeerId,
+        relays: &Vec<PeerId>,
+    ) -> bool {
+        let tip = match local_tip().await {
+            Ok(Some(tip)) => Some((tip.blockhash, tip.number)),
+            Ok(None) if CONFIG.sync_mode == SyncMode::Full => match derived_is_empty().await {
+                Ok(true) => match &CHAIN_SPEC.genesis {
+                    //genesis of chain spec doesn't need to be downloaded
+                    Some(genesis) => match insert_synced_block(genesis.clone()).await {
+                        Ok(_) => Some((genesis.header.blockhash.clone(), genesis.header.number)),
+                        Err(_) => None,
+                    },
+                    None => Some((GENESIS_PREVHASH.to_string(), -1)),
+                },
+                Ok(false) => {
+                    write_log("there are UTXOs or reciepts without blocks, use reset-db command before syncing");
+                    None
+                }
+                Err(_) => None,
+            },
+            _ => None,
+        };
+        match tip {
+            Some((tip_hash, tip_number)) => {
+                self.reset();
+                self.source = Some(source);
+                self.tip_hash = tip_hash;
+                self.tip_number = tip_number;
+                self.peers.push(source);
+                for relay in relays {
+                    if !self.peers.contains(relay) {
+                        self.peers.push(*relay);
+                    }
+                }
+                write_log(&format!(
+                    "block syncing started from block {} with {} peers",
+                    self.tip_number,
+                    self.peers.len()
+                ));
+                self.request_handshakes(swarm);
+                self.request_headers(swarm);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn handle_response(
+        &mut self,
+        request_id: OutboundRequestId,
+        response: Res,
+        swarm: &mut Swarm<CustomBehav>,
+    ) -> SyncProgress {
+        let (peer, request) = match self.pending.remove(&request_id) {
+            Some(pending) => pending,
+            None => return SyncProgress::NotMine,
+        };
+
+        match request {
+            SyncRequest::Handshake => {
+                //relays without pruned storage mode have all of the bodies
+                let bodies_from = match serde_json::from_str::<Handshake>(&response.res) {
+                    Ok(handshake) if handshake.pruned => {
+                        Some(handshake.oldest_body.unwrap_or(i64::MAX))
+                    }
+                    _ => None,
+                };
+                self.bodies_from.insert(peer, bodies_from);
+                self.dispatch(swarm)
+            }
+            SyncRequest::Headers => {
+                let headers = match serde_json::from_str::<Vec<BlockHeader>>(&response.res) {
+                    Ok(headers) => headers,
+                    Err(_) => {
+                        write_log("headers response deserializing problem! block_sync");
+                        return SyncProgress::Failed;
+                    }
+                };
+                let batch_len = headers.len() as i64;
+                for header in headers {
+                    if header.prevhash == GENESIS_PREVHASH
+                        && !CHAIN_SPEC.is_genesis(&header.blockhash)
+                    {
+                        write_log("genesis header is not the genesis of chain spec! block_sync");
+                        return SyncProgress::Failed;
+                    }
+                    if header.prevhash != self.tip_hash {
+                        write_log(&format!(
+                            "header {} is not linked to our chain! block_sync",
+                            header.number
+                        ));
+                        return SyncProgress::Failed;
+                    }
+                    self.tip_hash = header.blockhash.clone();
+                    self.tip_number = header.number;
+                    self.headers.push(header);
+                }
+
+                if batch_len == HEADERS_BATCH {
+                    self.request_headers(swarm);
+                    SyncProgress::Pending
+                } else if self.headers.is_empty() {
+                    write_log("block syncing: already at the tip");
+                    SyncProgress::Done
+                } else {
+                    write_log(&format!(
+                        "block syncing: {} headers verified, downloading blocks",
+                        self.headers.len()
+                    ));
+                    let mut start = 0;
+                    while start < self.headers.len() {
+                        self.queue.push_back(start);
+                        start += BLOCKS_BATCH as usize;
+                    }
+                    self.dispatch(swarm)
+                }
+            }
+            SyncRequest::Blocks(start) => {
+                match serde_json::from_str::<Vec<Block>>(&response.res) {
+                    Ok(blocks) if self.check_chunk(start, &blocks) => {
+                        self.arrived.insert(start, blocks);
+                    }
+                    _ => {
+                        write_log(&format!("wrong blocks from {} in block syncing", peer));
+                        return self.drop_peer(peer, start, swarm);
+                    }
+                }
+
+                //send arrived blocks to the inserter in order
+                while let Some(blocks) = self.arrived.remove(&self.next_apply) {
+                    self.next_apply += blocks.len();
+                    self.inserting += 1;
+                    let inserter = self.inserter.get_or_insert_with(|| {
+                        let (inserter, results) = spawn_inserter();
+                        self.inserter_results = Some(results);
+                        inserter
+                    });
+                    if inserter.send(blocks).is_err() {
+                        write_log("block inserter is not running! block_sync");
+                        return SyncProgress::Failed;
+                    }
+                }
+                self.dispatch(swarm)
+            }
+        }
+    }
+
+    //result of a chunk of the inserter (pending if nothing is inserting)
+    pub async fn next_inserted(&mut self) -> Result<usize, i64> {
+        match self.inserter_results.as_mut() {
+            Some(results) => match results.recv().await {
+                Some(result) => result,
+                None => Err(self.tip_number),
+            },
+            None => std::future::pending().await,
+        }
+    }
+
+    pub fn handle_inserted(
+        &mut self,
+        result: Result<usize, i64>,
+        swarm: &mut Swarm<CustomBehav>,
+    ) -> SyncProgress {
+        match result {
+            Ok(count) => {
+                self.inserting -= 1;
+                self.inserted += count;
+                if self.inserted == self.headers.len() {
+                    write_log(&format!(
+                        "block syncing completed at block {}",
+                        self.tip_number
+                    ));
+                    SyncProgress::Done
+                } else {
+                    self.dispatch(swarm)
+                }
+            }
+            Err(number) => {
+                write_log(&format!("inserting synced block {} problem", number));
+                SyncProgress::Failed
+            }
+        }
+    }
+
+    pub fn handle_failure(
+        &mut self,
+        request_id: OutboundRequestId,
+        swarm: &mut Swarm<CustomBehav>,
+    ) -> SyncProgress {
+        match self.pending.remove(&request_id) {
+            Some((peer, SyncRequest::Handshake)) => {
+                self.peers.retain(|p| *p != peer);
+                if self.peers.is_empty() {
+                    write_log("there is not any peer for block syncing");
+                    return SyncProgress::Failed;
+                }
+                self.dispatch(swarm)
+            }
+            Some((_, SyncRequest::Headers)) => SyncProgress::Failed,
+            Some((peer, SyncRequest::Blocks(start))) => self.drop_peer(peer, start, swarm),
+            None => SyncProgress::NotMine,
+        }
+    }
+
+    fn request_handshakes(&mut self, swarm: &mut Swarm<CustomBehav>) {
+        for peer in self.peers.clone() {
+            let req = Req {
+                req: "handshake".to_string(),
+            };
+            let request_id = swarm.behaviour_mut().req_res.send_request(&peer, req);
+            self.pending
+                .insert(request_id, (peer, SyncRequest::Handshake));
+        }
+    }
+
+    fn request_headers(&mut self, swarm: &mut Swarm<CustomBehav>) {
+        let headers_req = HeadersReq {
+            headers_from: self.tip_number + 1,
+            headers_to: self.tip_number + HEADERS_BATCH,
+        };
+        let source = self.peers[0];
+        let req = Req {
+            req: serde_json::to_string(&headers_req).unwrap(),
+        };
+        let request_id = swarm.behaviour_mut().req_res.send_request(&source, req);
+        self.pending
+            .insert(request_id, (source, SyncRequest::Headers));
+    }
+
+    //send waiting chunks to the peers that have their bodies and free slots
+    fn dispatch(&mut self, swarm: &mut Swarm<CustomBehav>) -> SyncProgress {
+        let mut waiting = VecDeque::new();
+        while let Some(start) = self.queue.pop_front() {
+            let requested = self
+                .pending
+                .values()
+                .filter(|(_, request)| matches!(request, SyncRequest::Blocks(_)))
+                .count();
+            if requested + self.arrived.len() + self.inserting >= MAX_BUFFERED_CHUNKS {
+                waiting.push_back(start);
+                continue;
+            }
+            let from = self.headers[start].number;
+            let free_peer = self.peers.iter().copied().find(|peer| {
+                self.has_bodies(peer, from)
+                    && self.pending.values().filter(|(p, _)| p == peer).count()
+                        < MAX_INFLIGHT_PER_PEER
+            });
+            match free_peer {
+                Some(peer) => {
+                    let end = (start + BLOCKS_BATCH as usize).min(self.headers.len()) - 1;
+                    let blocks_req = BlocksReq {
+                        blocks_from: from,
+                        blocks_to: self.headers[end].number,
+                    };
+                    let req = Req {
+                        req: serde_json::to_string(&blocks_req).unwrap(),
+                    };
+                    let request_id = swarm.behaviour_mut().req_res.send_request(&peer, req);
+                    self.pending
+                        .insert(request_id, (peer, SyncRequest::Blocks(start)));
+                }
+                None => {
+                    //peers that have not answered the handshake yet may have the bodies
+                    let may_have = self.peers.iter().any(|peer| {
+                        !self.bodies_from.contains_key(peer) || self.has_bodies(peer, from)
+                    });
+                    if !may_have {
+                        write_log(&format!(
+                            "there is not any peer with body of block {} for block syncing",
+                            from
+                        ));
+                        return SyncProgress::Failed;
+                    }
+                    waiting.push_back(start);
+                }
+            }
+        }
+        self.queue = waiting;
+        SyncProgress::Pending
+    }
+
+    //check if a peer has the bodies of blocks from a number (pruned relays only have new blocks)
+    fn has_bodies(&self, peer: &PeerId, from: i64) -> bool {
+        match self.bodies_from.get(peer) {
+            Some(Some(oldest_body)) => *oldest_body <= from,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    //stop asking a peer that sent wrong blocks or failed and give its chunk to the others
+    fn drop_peer(
+        &mut self,
+        peer: PeerId,
+        start: usize,
+        swarm: &mut Swarm<CustomBehav>,
+    ) -> SyncProgress {
+        self.peers.retain(|p| *p != peer);
+        self.queue.push_front(start);
+        for (_, (p, request)) in self.pending.iter() {
+            if *p == peer {
+                if let SyncRequest::Blocks(s) = request {
+                    self.queue.push_back(*s);
+                }
+            }
+        }
+        self.pending.retain(|_, (p, _)| *p != peer);
+        if self.peers.is_empty() {
+            write_log("there is not any peer for block syncing");
+            return SyncProgress::Failed;
+        }
+        self.dispatch(swarm)
+    }
+
+    //check blocks of a chunk with verified headers, body hashes and block signatures
+    fn check_chunk(&self, start: usize, blocks: &[Block]) -> bool {
+        let end = (start + BLOCKS_BATCH as usize).min(self.headers.len());
+        if blocks.len() != end - start {
+            return false;
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            if block.header != self.headers[start + i] {
+                return false;
+            }
+            let str_block_body = serde_json::to_string(&block.body).unwrap();
+            if create_hash(str_block_body) != block.header.blockhash {
+                return false;
+            }
+            if check_block_sign(block).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+//validate and insert chunks of blocks in order, it stops after a wrong block or when the block
+//syncing is reset
+fn spawn_inserter() -> (
+    mpsc::UnboundedSender<Vec<Block>>,
+    mpsc::UnboundedReceiver<Result<usize, i64>>,
+) {
+    let (inserter, mut chunks) = mpsc::unbounded_channel::<Vec<Block>>();
+    let (result_sender, results) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(blocks) = chunks.recv().await {
+            let count = blocks.len();
+            for block in blocks {
+                if result_sender.is_closed() {
+                    return;
+                }
+                let number = block.header.number;
+                if insert_synced_block(block).await.is_err() {
+                    let _ = result_sender.send(Err(number));
+                    return;
+                }
+            }
+            if result_sender.send(Ok(count)).is_err() {
+                return;
+            }
+        }
+    });
+    (inserter, results)
+}
+
+//get header of the last block in database
+pub async fn local_tip() -> Result<Option<BlockHeader>, ()> {
+    match blockchain_db().await {
+        Ok(db) => {
+            let blocks_coll: Collection<Document> = db.collection("Blocks");
+            let option = FindOneOptions::builder()
+                .sort(doc! {"header.number": -1})
+                .build();
+            match blocks_coll.find_one(None, option).await {
+                Ok(Some(doc)) => match from_document::<Block>(doc) {
+                    Ok(block) => Ok(Some(block.header)),
+                    Err(_) => Err(()),
+                },
+                Ok(None) => Ok(None),
+                Err(_) => Err(()),
+            }
+        }
+        Err(_) => Err(()),
+    }
+}
+
+//UTXOs and reciepts must be empty before making them from blocks
+async fn derived_is_empty() -> Result<bool, ()> {
+    match blockchain_db().await {
+        Ok(db) => {
+            let utxos_coll: Collection<Document> = db.collection("UTXOs");
+            let reciept_coll: Collection<Document> = db.collection("reciept");
+            match (
+                utxos_coll.find_one(None, None).await,
+                reciept_coll.find_one(None, None).await,
+            ) {
+                (Ok(utxo), Ok(reciept)) => Ok(utxo.is_none() && reciept.is_none()),
+                _ => Err(()),
+            }
+        }
+        Err(_) => Err(()),
+    }
+}
+
+//answer a headers request of another relay
+pub async fn headers_in_range(headers_req: HeadersReq) -> Vec<BlockHeader> {
+    let to = headers_req
+        .headers_to
+        .min(headers_req.headers_from + HEADERS_BATCH - 1);
+    //blocks are read before headers of pruned blocks, so a block that is pruned between them is in
+    //headers (a block is moved to Headers before it is removed from Blocks, it can be in both)
+    let blocks = find_blocks(headers_req.headers_from, to).await;
+    let mut headers = pruned_headers(headers_req.headers_from, to).await;
+    let last_pruned = headers.last().map(|header| header.number);
+    headers.extend(
+        blocks
+            .into_iter()
+            .map(|block| block.header)
+            .filter(|header| last_pruned.is_none_or(|number| header.number > number)),
+    );
+    headers
+}
+
+//answer a blocks request of another relay
+pub async fn blocks_in_range(blocks_req: BlocksReq) -> Vec<Block> {
+    let to = blocks_req
+        .blocks_to
+        .min(blocks_req.blocks_from + BLOCKS_BATCH - 1);
+    find_blocks(blocks_req.blocks_from, to).await
+}
+
+async fn find_blocks(from: i64, to: i64) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    match blockchain_db().await {
+        Ok(db) => {
+            let blocks_coll: Collection<Document> = db.collection("Blocks");
+            let filter = doc! {"header.number": {"$gte": from, "$lte": to}};
+            let option = FindOptions::builder()
+                .sort(doc! {"header.number": 1})
+                .build();
+            match blocks_coll.find(filter, option).await {
+                Ok(mut cursor) => {
+                    while let Some(Ok(doc)) = cursor.next().await {
+                        match from_document::<Block>(doc) {
+                            Ok(block) => blocks.push(block),
+                            Err(_) => break,
+                        }
+                    }
+                }
+                Err(_) => write_log("finding blocks for syncing problem! block_sync"),
+            }
+        }
+        Err(_) => write_log("database connection problem in block_sync"),
+    }
+    blocks
+}
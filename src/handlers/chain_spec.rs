@@ -0,0 +1,294 @@
+// Chain spec of the network that the relay belongs to, read from chainspec.json
+// (/etc/chainspec.json on linux). Without it the relay uses the built-in mainnet spec.
+// Genesis blocks are only accepted if the spec has the genesis block or its hash (genesis_hash).
+// The built-in mainnet spec doesn't have them: it trusts the genesis block of its database, or on
+// an empty database the first genesis block that it checks (the genesis of the relay that it
+// syncs with), and no other genesis block after it.
+//
+// Gossip topics ("relay", "client", "sse") and the request-response protocol are namespaced by
+// network_id and protocol versions, so relays and clients of different networks never talk.
+// Mainnet keeps the names that its relays and clients used before chain specs ("relay" and
+// "/mg/1.0"), other networks have the network_id prefix.
+// Topics of validators (peer id of their relay) are unique already and are not namespaced.
+// Kademlia of relays is namespaced by network_id too.
+//
+// Example:
+// {
+//   "network_id": "devnet-1",
+//   "genesis": { ...genesis block... },
+//   "rewards": { "block_reward": "50", "fee_rate": "0.01" },
+//   "protocol_versions": { "gossip": "1", "req_res": "1.0" }
+// }
+
+use std::{env::consts::OS, fs, str::FromStr};
+
+use libp2p::{gossipsub::IdentTopic, StreamProtocol};
+use mongodb::{
+    bson::{doc, from_document, Document},
+    Collection,
+};
+use once_cell::sync::{Lazy, OnceCell};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use super::{
+    create_log::write_log, db_connection::blockchain_db, recieved_block::create_hash,
+    structures::Block,
+};
+
+//prevhash of the genesis block
+pub const GENESIS_PREVHASH: &str = "This block is Genesis";
+
+//network id of the built-in spec
+const MAINNET: &str = "mainnet";
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RewardParams {
+    //reward of the validator for every block (none: reward of coinbase is not checked)
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub block_reward: Option<Decimal>,
+    //fee of a transaction is value * fee_rate
+    #[serde_as(as = "DisplayFromStr")]
+    pub fee_rate: Decimal,
+}
+
+impl Default for RewardParams {
+    fn default() -> Self {
+        Self {
+            block_reward: None,
+            fee_rate: Decimal::from_str("0.01").unwrap(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProtocolVersions {
+    pub gossip: String,
+    pub req_res: String,
+}
+
+impl Default for ProtocolVersions {
+    fn default() -> Self {
+        Self {
+            gossip: "1".to_string(),
+            req_res: "1.0".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ChainSpec {
+    pub network_id: String,
+    //genesis block of the network, its hash is the genesis hash
+    pub genesis: Option<Block>,
+    //hash of the genesis block if genesis block is not in the spec
+    //a genesis block with another hash is never accepted (empty: no genesis block is accepted)
+    pub genesis_hash: String,
+    pub rewards: RewardParams,
+    pub protocol_versions: ProtocolVersions,
+    //genesis hash that the built-in mainnet spec trusts
+    #[serde(skip)]
+    trusted_genesis: OnceCell<String>,
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        Self {
+            network_id: MAINNET.to_string(),
+            genesis: None,
+            genesis_hash: String::new(),
+            rewards: RewardParams::default(),
+            protocol_versions: ProtocolVersions::default(),
+            trusted_genesis: OnceCell::new(),
+        }
+    }
+}
+
+impl ChainSpec {
+    pub fn is_genesis(&self, blockhash: &str) -> bool {
+        if !self.genesis_hash.is_empty() {
+            return self.genesis_hash == blockhash;
+        }
+        //mainnet without a genesis hash trusts the first genesis
+        self.is_mainnet()
+            && !blockhash.is_empty()
+            && self.trusted_genesis.get_or_init(|| blockhash.to_string()) == blockhash
+    }
+
+    fn is_mainnet(&self) -> bool {
+        self.network_id == MAINNET
+    }
+
+    //gossip topic of this network
+    pub fn topic(&self, name: &str) -> IdentTopic {
+        if self.is_mainnet() {
+            return IdentTopic::new(name);
+        }
+        IdentTopic::new(format!(
+            "{}/{}/{}",
+            self.network_id, name, self.protocol_versions.gossip
+        ))
+    }
+
+    //request-response protocol of this network
+    pub fn req_res_protocol(&self) -> StreamProtocol {
+        let protocol = if self.is_mainnet() {
+            format!("/mg/{}", self.protocol_versions.req_res)
+        } else {
+            format!("/{}/mg/{}", self.network_id, self.protocol_versions.req_res)
+        };
+        StreamProtocol::try_from_owned(protocol).unwrap()
+    }
+
+    //kademlia protocol of relays of this network
+    pub fn kad_protocol(&self) -> StreamProtocol {
+        StreamProtocol::try_from_owned(format!("/{}/kad/1.0.0", self.network_id)).unwrap()
+    }
+
+    //identify protocol version of relays of this network
+    pub fn identify_protocol(&self) -> String {
+        format!("/{}/relay/1.0.0", self.network_id)
+    }
+
+    pub fn fee(&self, value: Decimal) -> Decimal {
+        value * self.rewards.fee_rate
+    }
+
+    //check reward of a coinbase with block reward of the network
+    pub fn check_reward(&self, block: &Block) -> bool {
+        match self.rewards.block_reward {
+            Some(reward) => block.body.coinbase.coinbase_data.reward == reward,
+            None => true,
+        }
+    }
+}
+
+pub static CHAIN_SPEC: Lazy<ChainSpec> = Lazy::new(load_chain_spec);
+
+//trust the genesis block of the database (mainnet without a genesis hash)
+pub async fn trust_stored_genesis() -> Result<(), String> {
+    if !CHAIN_SPEC.genesis_hash.is_empty() || !CHAIN_SPEC.is_mainnet() {
+        return Ok(());
+    }
+    let db = blockchain_db().await?;
+    let blocks_coll: Collection<Document> = db.collection("Blocks");
+    let filter = doc! {"header.prevhash": GENESIS_PREVHASH};
+    match blocks_coll.find_one(filter, None).await {
+        Ok(Some(genesis)) => {
+            let genesis: Block = from_document(genesis).map_err(|e| e.to_string())?;
+            CHAIN_SPEC.is_genesis(&genesis.header.blockhash);
+            write_log(&format!("genesis {} is trusted", genesis.header.blockhash));
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn load_chain_spec() -> ChainSpec {
+    let mut spec_path = "";
+    if OS == "linux" {
+        spec_path = "/etc/chainspec.json"
+    } else if OS == "windows" {
+        spec_path = "chainspec.json"
+    }
+    let mut spec = match fs::read_to_string(spec_path) {
+        Ok(str_spec) => match serde_json::from_str::<ChainSpec>(&str_spec) {
+            Ok(spec) => spec,
+            Err(e) => {
+                write_log(&format!(
+                    "chain spec problem, default chain spec is used: {}",
+                    e
+                ));
+                ChainSpec::default()
+            }
+        },
+        Err(_) => ChainSpec::default(),
+    };
+
+    //network id is a part of topics and protocol id
+    if spec.network_id.is_empty() || spec.network_id.contains('/') {
+        write_log("network id of chain spec is not valid, mainnet is used");
+        spec.network_id = ChainSpec::default().network_id;
+    }
+
+    //genesis hash comes from the genesis block
+    if let Some(genesis) = spec.genesis.clone() {
+        let str_body = serde_json::to_string(&genesis.body).unwrap();
+        if genesis.header.prevhash != GENESIS_PREVHASH
+            || create_hash(str_body) != genesis.header.blockhash
+        {
+            write_log("genesis block of chain spec is not valid");
+            spec.genesis = None;
+        } else if !spec.genesis_hash.is_empty() && spec.genesis_hash != genesis.header.blockhash {
+            write_log("genesis hash of chain spec is not equal to its genesis block");
+            spec.genesis = None;
+        } else {
+            spec.genesis_hash = genesis.header.blockhash;
+        }
+    }
+
+    if spec.genesis_hash.is_empty() && spec.is_mainnet() {
+        write_log("genesis hash is not set in chain spec, the first genesis block is trusted");
+    } else if spec.genesis_hash.is_empty() {
+        write_log("genesis hash is not set in chain spec, genesis blocks are not accepted");
+    }
+    write_log(&format!("network: {}", spec.network_id));
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devnet() -> ChainSpec {
+        serde_json::from_str(r#"{"network_id": "devnet-1", "protocol_versions": {"gossip": "2"}}"#)
+            .unwrap()
+    }
+
+    #[test]
+    fn mainnet_keeps_legacy_names() {
+        let mainnet = ChainSpec::default();
+        assert_eq!(mainnet.topic("relay").hash().as_str(), "relay");
+        assert_eq!(mainnet.topic("client").hash().as_str(), "client");
+        assert_eq!(mainnet.req_res_protocol().as_ref(), "/mg/1.0");
+    }
+
+    #[test]
+    fn other_networks_are_namespaced() {
+        let devnet = devnet();
+        assert_eq!(devnet.topic("relay").hash().as_str(), "devnet-1/relay/2");
+        assert_eq!(devnet.topic("sse").hash().as_str(), "devnet-1/sse/2");
+        assert_eq!(devnet.req_res_protocol().as_ref(), "/devnet-1/mg/1.0");
+        assert_eq!(devnet.kad_protocol().as_ref(), "/devnet-1/kad/1.0.0");
+        assert_ne!(
+            devnet.topic("relay").hash(),
+            ChainSpec::default().topic("relay").hash()
+        );
+    }
+
+    #[test]
+    fn mainnet_trusts_its_first_genesis() {
+        let mainnet = ChainSpec::default();
+        assert!(!mainnet.is_genesis(""));
+        assert!(mainnet.is_genesis("abc"));
+        assert!(mainnet.is_genesis("abc"));
+        assert!(!mainnet.is_genesis("abd"));
+    }
+
+    #[test]
+    fn genesis_needs_a_hash() {
+        let mut spec = devnet();
+        assert!(!spec.is_genesis(""));
+        assert!(!spec.is_genesis("abc"));
+        spec.genesis_hash = "abc".to_string();
+        assert!(spec.is_genesis("abc"));
+        assert!(!spec.is_genesis("abd"));
+        assert!(!spec.is_genesis(""));
+    }
+}
@@ -1,25 +1,34 @@
-use std::str::FromStr;
-
 use chrono::{SubsecRound, Utc};
 // use libp2p::gossipsub::Message;
-use rust_decimal::Decimal;
 use sha2::{Digest, Sha256};
 use sp_core::Pair;
 
 use super::{
+    chain_spec::CHAIN_SPEC,
     create_log::write_log,
     db_connection::blockchain_db,
     reciept::insert_reciept,
-    structures::{Transaction, UTXO},
+    structures::Transaction,
+    utxo_store::{add_utxo, find_utxo, spend_utxo},
 };
 
 use mongodb::{
-    bson::{doc, from_document, to_document, Document},
+    bson::{doc, Document},
     Collection,
 };
 
-pub async fn handle_transactions(message: String) {
+//check a transaction and insert its reciept (pending or error)
+//Err("reject") is for messages that are not new transactions or can't be checked now
+pub async fn handle_transactions<'a>(message: String) -> Result<(), &'a str> {
     if let Ok(mut transaction) = serde_json::from_str::<Transaction>(&message) {
+        //a transaction without signature or signer can't be checked or have a reciept
+        let (signature, signer) = match (
+            transaction.input.signatures.first(),
+            transaction.output.output_data.sigenr_public_keys.first(),
+        ) {
+            (Some(signature), Some(signer)) => (signature.clone(), *signer),
+            _ => return Err("transaction verify problem"),
+        };
         match blockchain_db().await {
             Ok(db) => {
                 let reciept_coll: Collection<Document> = db.collection("reciept");
@@ -28,8 +37,7 @@ pub async fn handle_transactions(message: String) {
                 match reciept {
                     Ok(is) => {
                         if is.is_none() {
-                            transaction.fee =
-                                transaction.value * Decimal::from_str("0.01").unwrap();
+                            transaction.fee = CHAIN_SPEC.fee(transaction.value);
                             //create hash of transaction
                             let mut check_hasher = Sha256::new();
                             check_hasher.update(transaction.input.input_hash.clone());
@@ -52,11 +60,8 @@ pub async fn handle_transactions(message: String) {
 
                             //check transaction signature
                             let signed_message = transaction.tx_hash.clone();
-                            let sign_verify = sp_core::ecdsa::Pair::verify(
-                                &transaction.input.signatures[0],
-                                signed_message,
-                                &transaction.output.output_data.sigenr_public_keys[0],
-                            );
+                            let sign_verify =
+                                sp_core::ecdsa::Pair::verify(&signature, signed_message, &signer);
 
                             //get bool as verify of hashs
                             let outputhash_check = output_hash == transaction.output.output_hash;
@@ -65,10 +70,7 @@ pub async fn handle_transactions(message: String) {
 
                             let mut unvalidity_num = 0 as usize;
                             for i in transaction.output.output_data.utxos.clone() {
-                                if i.output_unspent.public_key
-                                    == transaction.output.output_data.sigenr_public_keys[0]
-                                        .to_string()
-                                {
+                                if i.output_unspent.public_key == signer.to_string() {
                                     unvalidity_num += 1;
                                 }
                             }
@@ -82,55 +84,54 @@ pub async fn handle_transactions(message: String) {
                                 && inputhash_check
                                 && outputhash_check
                             {
-                                let filter = doc! {"public_key": &transaction.output.output_data.sigenr_public_keys[0].to_string()};
-                                let utxos_coll = db.collection("UTXOs");
-                                let utxo_doc =
-                                    utxos_coll.find_one(filter.clone(), None).await.unwrap();
-                                if let Some(doc) = utxo_doc {
-                                    let mut user_utxos: UTXO = from_document(doc).unwrap();
-                                    let mut correct_tx = true;
-                                    for utxo in transaction.input.input_data.utxos.clone() {
-                                        let index = user_utxos.utxos.iter().position(|u| {
-                                            *u.output_hash == utxo.output_hash.clone()
-                                        });
-                                        match index {
-                                            Some(i) => {
-                                                user_utxos.utxos.remove(i);
-                                            }
-                                            None => {
-                                                correct_tx = false;
-                                                break;
-                                            }
+                                let signer = signer.to_string();
+                                let utxos_coll: Collection<Document> = db.collection("UTXOs");
+                                //all inputs must be unspent outputs of the signer, spending them
+                                //is the check (workers run at the same time, so another
+                                //transaction with the same input finds it spent)
+                                let mut spent = Vec::new();
+                                let mut correct_tx = true;
+                                for utxo in transaction.input.input_data.utxos.iter() {
+                                    let record = match find_utxo(&utxos_coll, &utxo.output_hash)
+                                        .await
+                                    {
+                                        Ok(Some(record)) if record.public_key == signer => record,
+                                        _ => {
+                                            correct_tx = false;
+                                            break;
+                                        }
+                                    };
+                                    match spend_utxo(&utxos_coll, &signer, &utxo.output_hash).await
+                                    {
+                                        Ok(true) => spent.push(record),
+                                        _ => {
+                                            correct_tx = false;
+                                            break;
                                         }
                                     }
-
-                                    if correct_tx {
-                                        let user_utxo_updated = to_document(&user_utxos).unwrap();
-                                        utxos_coll
-                                            .replace_one(filter.clone(), user_utxo_updated, None)
-                                            .await
-                                            .unwrap();
-                                        //set fee
-                                        transaction.date.clear();
-                                        transaction
-                                            .date
-                                            .push_str(&Utc::now().round_subsecs(0).to_string());
-                                        insert_reciept(
-                                            transaction.clone(),
-                                            None,
-                                            "pending".to_string(),
-                                            "".to_string(),
-                                        )
-                                        .await;
-                                    } else {
-                                        insert_reciept(
-                                            transaction,
-                                            None,
-                                            "Error".to_string(),
-                                            "There is not input UTXOs".to_string(),
-                                        )
-                                        .await;
+                                }
+                                if !correct_tx {
+                                    //inputs of this transaction that are spent already go back
+                                    for record in spent {
+                                        if add_utxo(&utxos_coll, record).await.is_err() {
+                                            write_log("putting back input of transaction problem! check_trx");
+                                        }
                                     }
+                                }
+                                if correct_tx {
+                                    //set fee
+                                    transaction.date.clear();
+                                    transaction
+                                        .date
+                                        .push_str(&Utc::now().round_subsecs(0).to_string());
+                                    insert_reciept(
+                                        transaction.clone(),
+                                        None,
+                                        "pending".to_string(),
+                                        "".to_string(),
+                                    )
+                                    .await;
+                                    Ok(())
                                 } else {
                                     insert_reciept(
                                         transaction,
@@ -139,6 +140,8 @@ pub async fn handle_transactions(message: String) {
                                         "There is not input UTXOs".to_string(),
                                     )
                                     .await;
+                                    //inputs may be in a block that we don't have yet
+                                    Err("reject")
                                 }
                             } else {
                                 insert_reciept(
@@ -148,15 +151,21 @@ pub async fn handle_transactions(message: String) {
                                     "Transaction verify problem!".to_string(),
                                 )
                                 .await;
+                                Err("transaction verify problem")
                             }
+                        } else {
+                            Err("reject")
                         }
                     }
-                    Err(_) => {}
+                    Err(_) => Err("reject"),
                 }
             }
             Err(_) => {
                 write_log("database connection problem in check_trx.rs(line 158)");
+                Err("reject")
             }
         }
+    } else {
+        Err("reject")
     }
 }
@@ -0,0 +1,126 @@
+// Circuit relay v2 server
+//
+// Peers behind NAT reserve a slot in the relay and other peers reach them through circuits of the
+// relay. Only peers that have done the handshake request get reservations: the reservation rate
+// limiter of the relay behaviour denies other peers (they can try again after the handshake).
+// Limits of reservations and circuits come from the circuit_relay config and their counts are in
+// the metrics.
+//
+// DCUtR runs alongside the relay: relayed connections of this relay (circuits of other relays) are
+// upgraded to direct connections by hole punching, results are logged and counted in the metrics.
+
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use libp2p::{dcutr, relay, Multiaddr, PeerId};
+use once_cell::sync::Lazy;
+
+use super::{
+    config::CONFIG,
+    create_log::write_log,
+    metrics::{record_hole_punch, update_circuit_relay},
+};
+
+//connected peers that have done the handshake
+static HANDSHAKED: Lazy<Mutex<HashSet<PeerId>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+//peers that have a reservation
+static RESERVATIONS: Lazy<Mutex<HashSet<PeerId>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+pub fn relay_config() -> relay::Config {
+    let limits = &CONFIG.circuit_relay;
+    let mut config = relay::Config {
+        max_reservations: limits.max_reservations,
+        max_reservations_per_peer: limits.max_reservations_per_peer,
+        reservation_duration: Duration::from_secs(limits.reservation_duration_secs),
+        max_circuits: limits.max_circuits,
+        max_circuits_per_peer: limits.max_circuits_per_peer,
+        max_circuit_duration: Duration::from_secs(limits.max_circuit_duration_secs),
+        max_circuit_bytes: limits.max_circuit_bytes,
+        ..Default::default()
+    };
+    config
+        .reservation_rate_limiters
+        .push(Box::new(|peer_id: PeerId, _addr: &Multiaddr, _now| {
+            HANDSHAKED.lock().unwrap().contains(&peer_id)
+        }));
+    config
+}
+
+pub fn handshake_done(peer_id: PeerId) {
+    HANDSHAKED.lock().unwrap().insert(peer_id);
+}
+
+//all of the connections of the peer are closed, its reservation is removed by the relay
+pub fn peer_disconnected(peer_id: &PeerId) {
+    HANDSHAKED.lock().unwrap().remove(peer_id);
+    let mut reservations = RESERVATIONS.lock().unwrap();
+    if reservations.remove(peer_id) {
+        let active = reservations.len();
+        update_circuit_relay(|stats| stats.active_reservations = active);
+    }
+}
+
+pub fn handle_relay_event(event: relay::Event) {
+    match event {
+        relay::Event::ReservationReqAccepted { src_peer_id, .. } => {
+            let mut reservations = RESERVATIONS.lock().unwrap();
+            reservations.insert(src_peer_id);
+            let active = reservations.len();
+            update_circuit_relay(|stats| {
+                stats.reservations_accepted += 1;
+                stats.active_reservations = active;
+            });
+        }
+        relay::Event::ReservationReqDenied { src_peer_id } => {
+            write_log(&format!("relay reservation denied: {}", src_peer_id));
+            update_circuit_relay(|stats| stats.reservations_denied += 1);
+        }
+        relay::Event::ReservationTimedOut { src_peer_id } => {
+            let mut reservations = RESERVATIONS.lock().unwrap();
+            reservations.remove(&src_peer_id);
+            let active = reservations.len();
+            update_circuit_relay(|stats| stats.active_reservations = active);
+        }
+        relay::Event::CircuitReqAccepted { .. } => {
+            update_circuit_relay(|stats| {
+                stats.circuits_accepted += 1;
+                stats.active_circuits += 1;
+            });
+        }
+        relay::Event::CircuitReqDenied {
+            src_peer_id,
+            dst_peer_id,
+        } => {
+            write_log(&format!(
+                "relay circuit denied: {} -> {}",
+                src_peer_id, dst_peer_id
+            ));
+            update_circuit_relay(|stats| stats.circuits_denied += 1);
+        }
+        relay::Event::CircuitClosed { .. } => {
+            update_circuit_relay(|stats| {
+                stats.active_circuits = stats.active_circuits.saturating_sub(1)
+            });
+        }
+        _ => {}
+    }
+}
+
+pub fn handle_dcutr_event(event: dcutr::Event) {
+    match event.result {
+        Ok(_) => {
+            write_log(&format!(
+                "direct connection with {} by hole punching",
+                event.remote_peer_id
+            ));
+            record_hole_punch(Ok(()));
+        }
+        Err(e) => {
+            write_log(&format!(
+                "hole punching with {} failed: {}",
+                event.remote_peer_id, e
+            ));
+            record_hole_punch(Err(e.to_string()));
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::{env::consts::OS, fs};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use super::create_log::write_log;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    //import blocks only, validate all of them and make UTXOs and reciepts from them
+    Full,
+    //trust UTXOs and reciepts of the blockchain.zip of another relay
+    Snapshot,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    Mainnet,
+    //local test networks, several relays on one machine or LAN
+    Devnet,
+}
+
+//settings of relay that are read from relay.json, missing fields use default values
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RelayConfig {
+    pub profile: Profile,
+    pub sync_mode: SyncMode,
+    //make a new snapshot after this number of new blocks
+    pub snapshot_every_blocks: i64,
+    //or after this time if there is any new block
+    pub snapshot_every_minutes: u64,
+    //number of old snapshots that are kept in the snapshots directory
+    pub snapshots_to_keep: usize,
+    //pruned storage: keep bodies of this number of last blocks (none: keep all of the blocks)
+    pub prune_keep_blocks: Option<i64>,
+    pub prune_every_minutes: u64,
+    //max number of transactions and blocks that wait for storage workers
+    pub storage_queue_size: usize,
+    //number of tasks that check and save transactions
+    pub transaction_workers: usize,
+    pub peer_scoring: PeerScoring,
+    pub discovery: Discovery,
+    pub circuit_relay: CircuitRelay,
+}
+
+//circuit relay v2 server for peers behind NAT, only peers that have done the handshake get reservations
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct CircuitRelay {
+    pub enabled: bool,
+    pub max_reservations: usize,
+    pub max_reservations_per_peer: usize,
+    pub reservation_duration_secs: u64,
+    pub max_circuits: usize,
+    pub max_circuits_per_peer: usize,
+    pub max_circuit_duration_secs: u64,
+    //max bytes of a circuit in each direction
+    pub max_circuit_bytes: u64,
+}
+
+impl Default for CircuitRelay {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_reservations: 128,
+            max_reservations_per_peer: 4,
+            reservation_duration_secs: 3600,
+            max_circuits: 16,
+            max_circuits_per_peer: 4,
+            max_circuit_duration_secs: 120,
+            max_circuit_bytes: 1 << 17,
+        }
+    }
+}
+
+//finding other relays
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Discovery {
+    //get relays from centichain.org and send the address of relay to it
+    pub web_registry: bool,
+    //multiaddresses of relays (with /p2p/<peer id>) that are dialed and seed kademlia
+    pub bootstrap_peers: Vec<String>,
+    //time between kademlia walks that find new relays
+    pub discovery_every_minutes: u64,
+    //find relays of the local network with mdns (none: on in devnet profile only)
+    pub mdns: Option<bool>,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self {
+            web_registry: true,
+            bootstrap_peers: Vec::new(),
+            discovery_every_minutes: 5,
+            mdns: None,
+        }
+    }
+}
+
+//gossipsub score thresholds of peers and bans of peers with bad scores
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PeerScoring {
+    //no gossip (IHAVE, IWANT) with peers below this score
+    pub gossip_threshold: f64,
+    //our messages are not published to peers below this score
+    pub publish_threshold: f64,
+    //all of the messages of peers below this score are ignored
+    pub graylist_threshold: f64,
+    //peers below this score are banned
+    pub ban_threshold: f64,
+    //time of bans, for bad scores and wrong blocks
+    pub ban_minutes: u64,
+    //penalty of every invalid block or transaction in relay and client topics
+    pub invalid_message_weight: f64,
+    //peers of one IP address above this number get a penalty of (peers above it)^2 * weight
+    //(none: no penalty in devnet profile, relays of devnets share addresses)
+    pub ip_colocation_threshold: f64,
+    pub ip_colocation_weight: Option<f64>,
+}
+
+impl Default for PeerScoring {
+    fn default() -> Self {
+        Self {
+            gossip_threshold: -10.0,
+            publish_threshold: -50.0,
+            graylist_threshold: -80.0,
+            ban_threshold: -100.0,
+            ban_minutes: 60,
+            invalid_message_weight: -10.0,
+            ip_colocation_threshold: 10.0,
+            ip_colocation_weight: None,
+        }
+    }
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            profile: Profile::Mainnet,
+            sync_mode: SyncMode::Full,
+            snapshot_every_blocks: 100,
+            snapshot_every_minutes: 60,
+            snapshots_to_keep: 3,
+            prune_keep_blocks: None,
+            prune_every_minutes: 10,
+            storage_queue_size: 256,
+            transaction_workers: 4,
+            peer_scoring: PeerScoring::default(),
+            discovery: Discovery::default(),
+            circuit_relay: CircuitRelay::default(),
+        }
+    }
+}
+
+impl RelayConfig {
+    pub fn mdns_enabled(&self) -> bool {
+        self.discovery
+            .mdns
+            .unwrap_or(self.profile == Profile::Devnet)
+    }
+
+    pub fn ip_colocation_weight(&self) -> f64 {
+        match self.peer_scoring.ip_colocation_weight {
+            Some(weight) => weight,
+            None if self.profile == Profile::Devnet => 0.0,
+            None => -5.0,
+        }
+    }
+}
+
+pub static CONFIG: Lazy<RelayConfig> = Lazy::new(load_config);
+
+fn load_config() -> RelayConfig {
+    let mut config_path = "";
+    if OS == "linux" {
+        config_path = "/etc/relay.json"
+    } else if OS == "windows" {
+        config_path = "relay.json"
+    }
+    match fs::read_to_string(config_path) {
+        Ok(str_config) => match serde_json::from_str::<RelayConfig>(&str_config) {
+            Ok(config) => config,
+            Err(e) => {
+                write_log(&format!(
+                    "relay config problem, default config is used: {}",
+                    e
+                ));
+                RelayConfig::default()
+            }
+        },
+        Err(_) => RelayConfig::default(),
+    }
+}
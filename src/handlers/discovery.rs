@@ -0,0 +1,246 @@
+// Discovery of relays with kademlia
+//
+// Relays of a network run kademlia with their own protocol (CHAIN_SPEC.kad_protocol), so every peer
+// in the routing table is a relay. Kademlia is seeded with relays.dat and bootstrap peers of the
+// config. A synced relay with a public address adds it as an external address and provides the
+// relays key of the network, and walks of the DHT (bootstrap and providers of the relays key) find
+// other relays; providers that are not connected are dialed. Public addresses of found relays are
+// added to relays.dat (at most MAX_SAVED_RELAYS of them), so the next dialing uses them. The web registry (centichain.org) is only used if discovery.web_registry is on.
+//
+// With mdns (on in the devnet profile) libp2p peers of the local network are dialed when they are
+// found. Mdns doesn't tell the network of a peer, so identify does: dialed peers that speak neither
+// kademlia nor request-response of this network are disconnected and not dialed again. Private
+// addresses of local relays are added to relays.dat too when kademlia confirms them as relays.
+
+use std::{
+    collections::HashSet,
+    env::consts::OS,
+    fs::{self, OpenOptions},
+    io::{BufWriter, Write},
+    net::IpAddr,
+    sync::Mutex,
+};
+
+use libp2p::{
+    identify,
+    kad::{self, RecordKey},
+    multiaddr::Protocol,
+    swarm::dial_opts::DialOpts,
+    Multiaddr, PeerId, Swarm,
+};
+use once_cell::sync::Lazy;
+
+use super::{
+    chain_spec::CHAIN_SPEC, config::CONFIG, create_log::write_log,
+    handle_listeners::send_addr_to_server, swarm_config::CustomBehav,
+};
+
+//relays.dat doesn't grow with every relay of the DHT
+const MAX_SAVED_RELAYS: usize = 200;
+
+//peers of the local network that are dialed and not identified yet
+static LOCAL_DIALS: Lazy<Mutex<HashSet<PeerId>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+//peers of the local network that are not in this network
+static FOREIGN_PEERS: Lazy<Mutex<HashSet<PeerId>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+pub fn relays_path() -> &'static str {
+    if OS == "windows" {
+        "relays.dat"
+    } else {
+        "/etc/relays.dat"
+    }
+}
+
+//key that relays of the network provide in kademlia
+fn relays_key() -> RecordKey {
+    RecordKey::new(&format!("/{}/relays", CHAIN_SPEC.network_id))
+}
+
+//peer id of a multiaddress that ends with /p2p/<peer id>
+pub fn peer_of(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Some(peer_id),
+        _ => None,
+    }
+}
+
+fn is_public(addr: &Multiaddr) -> bool {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => {
+            !ip.is_private() && !ip.is_loopback() && !ip.is_unspecified() && !ip.is_link_local()
+        }
+        Some(Protocol::Ip6(ip)) => !IpAddr::V6(ip).is_loopback() && !ip.is_unspecified(),
+        Some(Protocol::Dns(_)) | Some(Protocol::Dns4(_)) | Some(Protocol::Dns6(_)) => true,
+        _ => false,
+    }
+}
+
+//addresses of relays.dat and bootstrap peers of the config
+pub fn known_relays() -> Vec<Multiaddr> {
+    let str_relays = fs::read_to_string(relays_path()).unwrap_or_default();
+    let mut relays: Vec<Multiaddr> = Vec::new();
+    for line in str_relays.lines().chain(
+        CONFIG
+            .discovery
+            .bootstrap_peers
+            .iter()
+            .map(|peer| peer.as_str()),
+    ) {
+        if let Ok(addr) = line.trim().parse::<Multiaddr>() {
+            if !relays.contains(&addr) {
+                relays.push(addr);
+            }
+        }
+    }
+    relays
+}
+
+//add a relay to relays.dat if its peer id is not in it and it is not full
+fn save_relay_address(peer_id: &PeerId, addr: &Multiaddr) {
+    let str_relays = fs::read_to_string(relays_path()).unwrap_or_default();
+    let saved = str_relays
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .count();
+    if saved >= MAX_SAVED_RELAYS || str_relays.contains(&peer_id.to_string()) {
+        return;
+    }
+    match OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(relays_path())
+    {
+        Ok(file) => {
+            let mut writer = BufWriter::new(file);
+            match writeln!(writer, "{}", addr) {
+                Ok(_) => write_log(&format!("new relay is found: {}", addr)),
+                Err(e) => write_log(&format!("saving new relay problem: {}", e)),
+            }
+        }
+        Err(e) => write_log(&format!("saving new relay problem: {}", e)),
+    }
+}
+
+pub fn seed_kademlia(swarm: &mut Swarm<CustomBehav>) {
+    let local_peer_id = *swarm.local_peer_id();
+    for addr in known_relays() {
+        match peer_of(&addr) {
+            Some(peer_id) if peer_id != local_peer_id => {
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+            }
+            _ => {}
+        }
+    }
+}
+
+//announce public address of a synced relay in kademlia and the web registry
+pub async fn announce_address(swarm: &mut Swarm<CustomBehav>, full_addr: &str) {
+    if let Ok(mut addr) = full_addr.parse::<Multiaddr>() {
+        if peer_of(&addr).is_some() {
+            addr.pop();
+        }
+        swarm.add_external_address(addr);
+        if let Err(e) = swarm.behaviour_mut().kademlia.start_providing(relays_key()) {
+            write_log(&format!("providing relays key problem: {}", e));
+        }
+    }
+    if CONFIG.discovery.web_registry {
+        send_addr_to_server(full_addr.to_string()).await;
+    }
+}
+
+//walk the DHT to find new relays
+pub fn discover_relays(swarm: &mut Swarm<CustomBehav>) {
+    let kademlia = &mut swarm.behaviour_mut().kademlia;
+    if kademlia.bootstrap().is_ok() {
+        kademlia.get_providers(relays_key());
+    }
+}
+
+pub fn handle_kademlia_event(swarm: &mut Swarm<CustomBehav>, event: kad::Event) {
+    match event {
+        kad::Event::RoutingUpdated {
+            peer, addresses, ..
+        } => {
+            let local = CONFIG.mdns_enabled();
+            if let Some(addr) = addresses.iter().find(|addr| local || is_public(addr)) {
+                let mut full_addr = addr.clone();
+                if peer_of(&full_addr).is_none() {
+                    full_addr.push(Protocol::P2p(peer));
+                }
+                save_relay_address(&peer, &full_addr);
+            }
+        }
+        //providers of the relays key are relays, kademlia knows their addresses from the walk
+        kad::Event::OutboundQueryProgressed {
+            result:
+                kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders {
+                    providers,
+                    ..
+                })),
+            ..
+        } => {
+            let local_peer_id = *swarm.local_peer_id();
+            for provider in providers {
+                if provider == local_peer_id || swarm.is_connected(&provider) {
+                    continue;
+                }
+                if let Err(e) = swarm.dial(provider) {
+                    write_log(&format!("dialing provider {} problem: {}", provider, e));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+//listen addresses of relays (peers with kademlia of this network) go to kademlia,
+//so relays that have dialed this relay are found by others too
+pub fn handle_identify_event(swarm: &mut Swarm<CustomBehav>, event: identify::Event) {
+    if let identify::Event::Received { peer_id, info } = event {
+        let local_dial = LOCAL_DIALS.lock().unwrap().remove(&peer_id);
+        if !info.protocols.contains(&CHAIN_SPEC.kad_protocol()) {
+            //clients of this network (validators) speak request-response only
+            if local_dial && !info.protocols.contains(&CHAIN_SPEC.req_res_protocol()) {
+                write_log(&format!("local peer {} is not in this network", peer_id));
+                FOREIGN_PEERS.lock().unwrap().insert(peer_id);
+                let _ = swarm.disconnect_peer_id(peer_id);
+            }
+            return;
+        }
+        let local = CONFIG.mdns_enabled();
+        for addr in info.listen_addrs {
+            if local || is_public(&addr) {
+                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+            }
+        }
+    }
+}
+
+//dial peers of the local network that mdns has found, identify checks their network
+pub fn handle_mdns_event(swarm: &mut Swarm<CustomBehav>, found: Vec<(PeerId, Multiaddr)>) {
+    let mut peers: Vec<(PeerId, Vec<Multiaddr>)> = Vec::new();
+    for (peer_id, addr) in found {
+        match peers.iter_mut().find(|(peer, _)| peer == &peer_id) {
+            Some((_, addrs)) => addrs.push(addr),
+            None => peers.push((peer_id, vec![addr])),
+        }
+    }
+    for (peer_id, addrs) in peers {
+        if swarm.is_connected(&peer_id) || FOREIGN_PEERS.lock().unwrap().contains(&peer_id) {
+            continue;
+        }
+        let dial = DialOpts::peer_id(peer_id).addresses(addrs).build();
+        match swarm.dial(dial) {
+            Ok(_) => {
+                LOCAL_DIALS.lock().unwrap().insert(peer_id);
+                write_log(&format!("dialing with local peer: {}", peer_id));
+            }
+            Err(e) => write_log(&format!(
+                "dialing problem with local peer {}: {}",
+                peer_id, e
+            )),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    time::Duration,
+};
+
+use futures::StreamExt;
+use libp2p::PeerId;
+use reqwest::{header::RANGE, Client, StatusCode};
+use tokio::time::{sleep, timeout};
+
+use super::{
+    create_log::write_log,
+    snapshot::{
+        file_checksum, sync_archive_path, verify_manifest, ManifestRes, SignedManifest,
+        SnapshotFile,
+    },
+};
+
+//attempts for downloading from one relay before going to the next relay
+const MAX_ATTEMPTS: u32 = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+//max time between two chunks of the response
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(30);
+
+//download a verified blockchain.zip from the first relay that can give it, the others are mirrors
+pub async fn download_from_relays(dialed_addrs: &[String]) -> Option<SignedManifest> {
+    for dialed_addr in dialed_addrs {
+        let ip = match dialed_addr.trim_start_matches("/ip4/").split('/').next() {
+            Some(ip) => ip,
+            None => continue,
+        };
+
+        //get signed manifest of the relay snapshot before trusting it
+        let signed = match get_manifest(ip, dialed_addr).await {
+            Ok(signed) => signed,
+            Err(e) => {
+                write_log(&format!("snapshot manifest problem with {}: {}", ip, e));
+                continue;
+            }
+        };
+        write_log(&format!(
+            "snapshot manifest of block {} from {} verified",
+            signed.info.manifest.height, ip
+        ));
+
+        match download_snapshot(ip, &signed.info.archive).await {
+            Ok(_) => return Some(signed),
+            Err(e) => write_log(&format!("downloading snapshot from {} problem: {}", ip, e)),
+        }
+    }
+    None
+}
+
+//get signed manifest of a relay snapshot and check that it is signed by that relay
+async fn get_manifest(ip: &str, dialed_addr: &str) -> Result<SignedManifest, String> {
+    let relay: PeerId = match dialed_addr.split("/p2p/").nth(1) {
+        Some(peer_id) => peer_id.parse().map_err(|_| "relay peer id problem")?,
+        None => return Err("there is not peer id in relay address".to_string()),
+    };
+    let client = Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(CHUNK_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let manifest_addr = format!("http://{}:33369/snapshot/manifest", ip);
+    let response = client
+        .get(manifest_addr)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let manifest_res: ManifestRes = response.json().await.map_err(|e| e.to_string())?;
+    match manifest_res.signed {
+        Some(signed) => {
+            verify_manifest(&signed, &relay)?;
+            Ok(signed)
+        }
+        None => Err(manifest_res.status),
+    }
+}
+
+//download blockchain.zip of a relay with retries and resume, then check it with the manifest
+async fn download_snapshot(ip: &str, archive: &SnapshotFile) -> Result<(), String> {
+    let url = format!("http://{}:33369/snapshot/blockchain.zip", ip);
+    //part files are kept per archive checksum so a download only resumes the same archive
+    let part_path = format!(
+        "{}.{}.part",
+        sync_archive_path(),
+        &archive.sha256[..archive.sha256.len().min(16)]
+    );
+    let client = Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match download_part(&client, &url, &part_path, archive.size).await {
+            Ok(_) => break,
+            Err(e) => {
+                write_log(&format!(
+                    "downloading blockchain.zip problem (attempt {}): {}",
+                    attempt, e
+                ));
+                if attempt >= MAX_ATTEMPTS {
+                    return Err("too many failed attempts".to_string());
+                }
+                sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+        }
+    }
+
+    //check the whole file before using it
+    let downloaded = file_checksum(&part_path, &archive.name)?;
+    if downloaded != *archive {
+        fs::remove_file(&part_path).ok();
+        return Err("blockchain.zip is not equal to the signed manifest".to_string());
+    }
+    fs::rename(&part_path, sync_archive_path()).map_err(|e| e.to_string())
+}
+
+//size of the part file that downloading continues from
+//(a part that is bigger than the archive is not a part of it and it is removed)
+fn resume_offset(part_path: &str, size: u64) -> Result<u64, String> {
+    let downloaded = fs::metadata(part_path).map(|m| m.len()).unwrap_or(0);
+    if downloaded > size {
+        fs::remove_file(part_path).map_err(|e| e.to_string())?;
+        return Ok(0);
+    }
+    Ok(downloaded)
+}
+
+//open the part file for the body of a range request
+fn open_part(status: StatusCode, part_path: &str, downloaded: &mut u64) -> Result<File, String> {
+    match status {
+        StatusCode::PARTIAL_CONTENT => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(part_path)
+            .map_err(|e| e.to_string()),
+        StatusCode::OK => {
+            //relay doesn't support range requests, start from the beginning
+            *downloaded = 0;
+            File::create(part_path).map_err(|e| e.to_string())
+        }
+        status => Err(format!("relay response status: {}", status)),
+    }
+}
+
+//continue downloading from the end of the part file with a http range request
+async fn download_part(
+    client: &Client,
+    url: &str,
+    part_path: &str,
+    size: u64,
+) -> Result<(), String> {
+    let mut downloaded = resume_offset(part_path, size)?;
+    if downloaded == size {
+        return Ok(());
+    }
+
+    let response = client
+        .get(url)
+        .header(RANGE, format!("bytes={}-", downloaded))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut part = open_part(response.status(), part_path, &mut downloaded)?;
+    if downloaded > 0 {
+        write_log(&format!(
+            "resume downloading blockchain.zip from byte {}",
+            downloaded
+        ));
+    }
+
+    let mut body = response.bytes_stream();
+    let mut logged_tenth = downloaded * 10 / size.max(1);
+    loop {
+        match timeout(CHUNK_TIMEOUT, body.next()).await {
+            Ok(Some(Ok(chunk))) => {
+                part.write_all(&chunk).map_err(|e| e.to_string())?;
+                downloaded += chunk.len() as u64;
+                let tenth = downloaded * 10 / size.max(1);
+                if tenth > logged_tenth {
+                    logged_tenth = tenth;
+                    write_log(&format!(
+                        "downloading blockchain.zip: {}% ({} of {} bytes)",
+                        tenth * 10,
+                        downloaded,
+                        size
+                    ));
+                }
+            }
+            Ok(Some(Err(e))) => return Err(e.to_string()),
+            Ok(None) => break,
+            Err(_) => return Err("relay did not send any data in 30 seconds".to_string()),
+        }
+    }
+
+    if downloaded == size {
+        Ok(())
+    } else {
+        Err(format!("downloaded {} of {} bytes", downloaded, size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //part file of a test in the temp directory, it doesn't exist at the start
+    fn part_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "relay-download-{}-{}.part",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn resumes_from_the_end_of_the_part() {
+        let path = part_path("resume");
+        assert_eq!(resume_offset(&path, 10), Ok(0));
+        fs::write(&path, b"12345").unwrap();
+        assert_eq!(resume_offset(&path, 10), Ok(5));
+        assert_eq!(resume_offset(&path, 5), Ok(5));
+
+        //part of another archive
+        assert_eq!(resume_offset(&path, 4), Ok(0));
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn appends_partial_content() {
+        let path = part_path("partial");
+        fs::write(&path, b"12345").unwrap();
+        let mut downloaded = 5;
+        let mut part = open_part(StatusCode::PARTIAL_CONTENT, &path, &mut downloaded).unwrap();
+        part.write_all(b"678").unwrap();
+        assert_eq!(downloaded, 5);
+        assert_eq!(fs::read(&path).unwrap(), b"12345678");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn restarts_without_range_support() {
+        let path = part_path("whole");
+        fs::write(&path, b"12345").unwrap();
+        let mut downloaded = 5;
+        let mut part = open_part(StatusCode::OK, &path, &mut downloaded).unwrap();
+        part.write_all(b"abc").unwrap();
+        assert_eq!(downloaded, 0);
+        assert_eq!(fs::read(&path).unwrap(), b"abc");
+
+        let mut downloaded = 3;
+        assert!(open_part(StatusCode::NOT_FOUND, &path, &mut downloaded).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,16 @@
 use std::{fs::OpenOptions, io::{BufWriter, Write}};
 
 use libp2p::{
-    gossipsub::{IdentTopic, Message}, Multiaddr, PeerId, Swarm
+    gossipsub::{IdentTopic, Message, MessageId}, Multiaddr, PeerId, Swarm
 };
 
 use crate::handlers::structures::{ImSync, OutNode};
 
-use super::{create_log::write_log, get_addresses::get_addresses, handle_messages::msg_check, structures::{FullNodes, NextLeader}, CustomBehav};
+use super::{create_log::write_log, get_addresses::get_addresses, handle_messages::msg_check, storage_pipeline::StoragePipeline, structures::{FullNodes, NextLeader}, CustomBehav};
 
 pub async fn handle_gossip_message(
     propagation_source: PeerId,
+    message_id: MessageId,
     local_peer_id: PeerId,
     message: Message,
     clients: &mut Vec<PeerId>,
@@ -21,9 +22,10 @@ pub async fn handle_gossip_message(
     my_addresses: &mut Vec<String>,
     leader: &mut String, 
     fullnodes: &mut Vec<FullNodes>,
+    pipeline: &mut StoragePipeline,
 ) {
     
-    msg_check(message.clone(), leader, fullnodes, relays, propagation_source, swarm, connections, local_peer_id).await;
+    msg_check(message.clone(), message_id, propagation_source, leader, fullnodes, swarm, pipeline);
 
     match String::from_utf8(message.data.clone()) {
         Ok(msg) => {
@@ -1,22 +1,37 @@
 use std::net::Ipv4Addr;
-use std::process::Command;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use libp2p::core::transport::ListenerId;
 use libp2p::futures::StreamExt;
 use libp2p::Multiaddr;
 use libp2p::{gossipsub::IdentTopic, request_response::Event, swarm::SwarmEvent, PeerId, Swarm};
+use tokio::task::JoinHandle;
 
+use super::ban_list::close_banned_connections;
+use super::block_sync::{BlockSync, SyncProgress};
+use super::circuit_relay::{
+    handle_dcutr_event, handle_relay_event, handshake_done, peer_disconnected,
+};
+use super::config::{Profile, SyncMode, CONFIG};
 use super::create_log::write_log;
+use super::discovery::{
+    announce_address, discover_relays, handle_identify_event, handle_kademlia_event,
+    handle_mdns_event,
+};
 use super::get_addresses::get_addresses;
 use super::gossip_messages::handle_gossip_message;
-use super::handle_listeners::{handle, send_addr_to_server};
+use super::handle_listeners::{handle, handle_confirmed};
+use super::handle_messages::{handle_block_result, handle_transaction_result, report_validation};
 use super::outnodes::handle_outnode;
-use super::reciept::insert_reciept;
-use super::recieved_block::verifying_block;
+use super::peer_scoring::check_scores;
+use super::reachability::{add_confirmed_address, remove_confirmed_address, set_nat_status};
 use super::remove_relays::remove_peer;
 use super::requests::handle_requests;
 use super::send_address::send_address;
+use super::storage_pipeline::{
+    BlockJob, BlockOrigin, GossipSource, StoragePipeline, StorageResult,
+};
 use super::structures::{
     FullNodes, GetGossipMsg, GossipMessage, Req, Transaction,
 };
@@ -28,6 +43,42 @@ pub struct Listeners {
     pub id: Vec<ListenerId>,
 }
 
+//the swarm loop waits for swarm events and results of the storage workers together
+enum LoopEvent {
+    Swarm(Box<SwarmEvent<CustomBehavEvent>>),
+    Storage(StorageResult),
+    CheckScores,
+    DiscoverRelays,
+    //blocks of block syncing are inserted (count) or a block could not be inserted (number)
+    SyncInserted(Result<usize, i64>),
+    SnapshotSynced(PeerId, Result<(), ()>),
+}
+
+//snapshot syncing (download and import of blockchain.zip) runs in a task out of the swarm loop
+struct SnapshotSync {
+    source: PeerId,
+    task: JoinHandle<Result<(), ()>>,
+}
+
+//result of the snapshot syncing, it is pending if there is not any
+async fn snapshot_synced(snapshot_sync: &mut Option<SnapshotSync>) -> (PeerId, Result<(), ()>) {
+    let result = match snapshot_sync.as_mut() {
+        Some(sync) => {
+            let source = sync.source;
+            match (&mut sync.task).await {
+                Ok(result) => (source, result),
+                Err(e) => {
+                    write_log(&format!("snapshot syncing stopped: {}", e));
+                    (source, Err(()))
+                }
+            }
+        }
+        None => std::future::pending().await,
+    };
+    *snapshot_sync = None;
+    result
+}
+
 pub async fn events(
     swarm: Arc<Mutex<Swarm<CustomBehav>>>,
     local_peer_id: PeerId,
@@ -90,11 +141,149 @@ async fn handle_new_swarm_events(
 ) {
     let mut listeners = Listeners { id: Vec::new() };
     let mut in_syncing = false;
+    let mut block_sync = BlockSync::new();
+    let mut sync_addrs: Vec<String> = Vec::new();
+    let mut snapshot_sync: Option<SnapshotSync> = None;
+    //gossip blocks of syncing that are in the pipeline, the relay is synced after all of them
+    let mut syncing_left: Option<usize> = None;
+    let mut syncing_failed = false;
     let mut swarm = swarm.lock().unwrap();
+    let mut pipeline = StoragePipeline::spawn();
+    let mut scores_interval = tokio::time::interval(Duration::from_secs(10));
+    let mut discovery_interval = tokio::time::interval(Duration::from_secs(
+        CONFIG.discovery.discovery_every_minutes.max(1) * 60,
+    ));
 
     //check swarm events that come from libp2p
     loop {
-        match swarm.select_next_some().await {
+        let loop_event = tokio::select! {
+            event = swarm.select_next_some() => LoopEvent::Swarm(Box::new(event)),
+            Some(storage_result) = pipeline.next_result() => LoopEvent::Storage(storage_result),
+            _ = scores_interval.tick() => LoopEvent::CheckScores,
+            _ = discovery_interval.tick() => LoopEvent::DiscoverRelays,
+            result = block_sync.next_inserted() => LoopEvent::SyncInserted(result),
+            (source, result) = snapshot_synced(&mut snapshot_sync) => {
+                LoopEvent::SnapshotSynced(source, result)
+            }
+        };
+        let event = match loop_event {
+            LoopEvent::Swarm(event) => *event,
+            LoopEvent::Storage(storage_result) => {
+                let syncing_result = match storage_result {
+                    StorageResult::Block(block_result) => {
+                        let syncing_result = match block_result.job.origin {
+                            BlockOrigin::Syncing => Some(block_result.result),
+                            _ => None,
+                        };
+                        handle_block_result(
+                            *block_result,
+                            &mut swarm,
+                            leader,
+                            fullnodes,
+                            relays,
+                            clients,
+                            connections,
+                        )
+                        .await;
+                        pipeline.block_done(leader, fullnodes);
+                        syncing_result
+                    }
+                    StorageResult::Transaction(tx_result) => {
+                        handle_transaction_result(tx_result, &mut swarm);
+                        None
+                    }
+                    StorageResult::Response { channel, response } => {
+                        let _ = swarm
+                            .behaviour_mut()
+                            .req_res
+                            .send_response(channel, response);
+                        None
+                    }
+                    //only blocks of syncing are in the pipeline while syncing
+                    StorageResult::BlockLost => {
+                        pipeline.block_done(leader, fullnodes);
+                        syncing_left.map(|_| Err("block worker stopped"))
+                    }
+                };
+                let left = match (syncing_result, syncing_left.as_mut()) {
+                    (Some(result), Some(left)) => {
+                        if let Err(e) = result {
+                            if e != "reject" {
+                                write_log(&format!("block insert error in syncing blocks: {}", e));
+                                syncing_failed = true;
+                            }
+                        }
+                        *left -= 1;
+                        *left
+                    }
+                    _ => continue,
+                };
+                if left > 0 {
+                    continue;
+                }
+                syncing_left = None;
+                if !syncing_failed {
+                    synced(&mut swarm, my_addresses, &clients_topic, sync).await;
+                    continue;
+                }
+                for connected in connections.clone() {
+                    let _ = swarm.disconnect_peer_id(connected);
+                }
+                leader.clear();
+                fullnodes.clear();
+                connections.clear();
+                client_topic_subscriber.clear();
+                relay_topic_subscribers.clear();
+                clients.clear();
+                relays.clear();
+                dialed_addr.clear();
+                syncing_blocks.clear();
+                my_addresses.clear();
+                *sync = false;
+                break;
+            }
+            LoopEvent::SyncInserted(result) => {
+                let progress = block_sync.handle_inserted(result, &mut swarm);
+                finish_block_sync(
+                    progress,
+                    &mut block_sync,
+                    &mut swarm,
+                    &sync_addrs,
+                    &mut in_syncing,
+                    &mut snapshot_sync,
+                );
+                continue;
+            }
+            LoopEvent::SnapshotSynced(source, result) => {
+                match result {
+                    Ok(_) => {
+                        write_log("syncing completed");
+                        let fullnodes_req = Req {
+                            req: "fullnodes".to_string(),
+                        };
+                        swarm
+                            .behaviour_mut()
+                            .req_res
+                            .send_request(&source, fullnodes_req);
+                    }
+                    Err(_) => {
+                        write_log("snapshot syncing error");
+                        in_syncing = false;
+                    }
+                }
+                continue;
+            }
+            LoopEvent::CheckScores => {
+                check_scores(&mut swarm);
+                close_banned_connections(&mut swarm);
+                continue;
+            }
+            LoopEvent::DiscoverRelays => {
+                discover_relays(&mut swarm);
+                continue;
+            }
+        };
+        match event {
             SwarmEvent::NewListenAddr {
                 address,
                 listener_id,
@@ -102,10 +291,12 @@ async fn handle_new_swarm_events(
                 let str_addr = address.clone().to_string();
                 let ipv4 = str_addr.split("/").nth(2).unwrap();
                 let ip: Ipv4Addr = ipv4.parse().unwrap();
-                if !ip.is_private() && ipv4 != "127.0.0.1" {
+                //relays of devnets are in local networks
+                let local_ok = CONFIG.profile == Profile::Devnet;
+                if (!ip.is_private() || local_ok) && ipv4 != "127.0.0.1" {
                     handle(address, local_peer_id, my_addresses).await;
                     if *sync {
-                        send_addr_to_server(my_addresses[0].clone()).await;
+                        announce_address(&mut swarm, &my_addresses[0].clone()).await;
                     }
                 } else {
                     write_log("Find private IP!");
@@ -113,33 +304,33 @@ async fn handle_new_swarm_events(
 
                 listeners.id.push(listener_id);
             }
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+            //addresses that other peers have dialed back (autonat)
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                write_log(&format!("external address confirmed: {}", address));
+                add_confirmed_address(&address);
+                handle_confirmed(address, local_peer_id, my_addresses);
                 if *sync {
-                    match Command::new("mongodump")
-                        .arg("--db")
-                        .arg("Blockchain")
-                        .arg("--out")
-                        .arg("/etc/dump")
-                        .output()
-                    {
-                        Ok(_) => {
-                            match Command::new("zip")
-                                .arg("-r")
-                                .arg("/home/blockchain.zip")
-                                .arg("/etc/dump/Blockchain")
-                                .output()
-                            {
-                                Ok(_) => {}
-                                Err(e) => write_log(&format!("{:?}", e)),
-                            }
-                        }
-                        Err(e) => write_log(&format!("{:?}", e)),
-                    }
+                    announce_address(&mut swarm, &my_addresses[0].clone()).await;
                 }
+            }
+            SwarmEvent::ExternalAddrExpired { address } => {
+                write_log(&format!("external address expired: {}", address));
+                remove_confirmed_address(&address);
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
                 connections.push(peer_id);
                 swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
             }
             SwarmEvent::OutgoingConnectionError { peer_id, .. } => {
+                //failed dials of kademlia are not dials of relays
+                let dialed = peer_id.is_some_and(|peer_id| {
+                    dialed_addr
+                        .iter()
+                        .any(|dialed| dialed.contains(&peer_id.to_string()))
+                });
+                if !dialed {
+                    continue;
+                }
                 write_log(&format!("dialing failed with: {}", peer_id.unwrap()));
                 remove_peer(peer_id.unwrap()).await;
                 let dialed_index = dialed_addr
@@ -166,7 +357,14 @@ async fn handle_new_swarm_events(
                     break;
                 }
             }
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                ..
+            } => {
+                if num_established == 0 {
+                    peer_disconnected(&peer_id);
+                }
                 if client_topic_subscriber.contains(&peer_id) {
                     write_log(&format!("connection closed with: {}", peer_id));
                     let index = client_topic_subscriber.iter().position(|c| *c == peer_id);
@@ -255,13 +453,14 @@ async fn handle_new_swarm_events(
                 CustomBehavEvent::Gossipsub(gossipevent) => match gossipevent {
                     libp2p::gossipsub::Event::Message {
                         propagation_source,
+                        message_id,
                         message,
-                        ..
                     } => {
                         let str_msg = String::from_utf8(message.data.clone()).unwrap();
                         if *sync {
                             handle_gossip_message(
                                 propagation_source,
+                                message_id,
                                 local_peer_id,
                                 message,
                                 clients,
@@ -273,13 +472,38 @@ async fn handle_new_swarm_events(
                                 my_addresses,
                                 leader,
                                 fullnodes,
+                                &mut pipeline,
                             )
                             .await;
                         } else {
                             write_log("gossip message recieved while not syncing");
                             write_log(&format!("gossip message: {:?}", str_msg));
+                            //blocks and transactions can't be checked before syncing, other relays forward them
+                            let source = GossipSource {
+                                message_id,
+                                propagation_source,
+                            };
+                            if serde_json::from_str::<GossipMessage>(&str_msg).is_ok()
+                                || serde_json::from_str::<Transaction>(&str_msg).is_ok()
+                            {
+                                report_validation(&mut swarm, &source, &Err("reject"));
+                            } else {
+                                report_validation(&mut swarm, &source, &Ok(()));
+                            }
                             if let Ok(gossipmsg) = serde_json::from_str::<GossipMessage>(&str_msg) {
                                 write_log("gossip message recieved while syncing is GossipMessage");
+                                //blocks of syncing are in the pipeline already, this one goes after them
+                                if let Some(left) = syncing_left.as_mut() {
+                                    let job = BlockJob {
+                                        str_msg: str_msg.clone(),
+                                        gossip_message: gossipmsg,
+                                        origin: BlockOrigin::Syncing,
+                                    };
+                                    if pipeline.push_block(job, leader, fullnodes) {
+                                        *left += 1;
+                                    }
+                                    continue;
+                                }
                                 let new_gossip = GetGossipMsg {
                                     gossip: gossipmsg.clone(),
                                     propagation_source: propagation_source,
@@ -291,16 +515,11 @@ async fn handle_new_swarm_events(
                                     gossipmsg.block.header.blockhash
                                 ));
                             }
-                            if let Ok(transaction) = serde_json::from_str::<Transaction>(&str_msg) {
+                            if serde_json::from_str::<Transaction>(&str_msg).is_ok() {
                                 write_log("gossip message recieved while syncing is Transaction");
-                                insert_reciept(
-                                    transaction,
-                                    None,
-                                    "pending".to_string(),
-                                    "".to_string(),
-                                )
-                                .await;
-                                write_log("reciept inserted while syncing");
+                                if pipeline.push_pending_reciept(str_msg.clone()) {
+                                    write_log("reciept of transaction queued while syncing");
+                                }
                             }
                             if let Ok(addresses) = serde_json::from_str::<Vec<String>>(&str_msg) {
                                 get_addresses(addresses, local_peer_id, my_addresses);
@@ -314,27 +533,28 @@ async fn handle_new_swarm_events(
                                 }
                                 if !*sync && !in_syncing {
                                     in_syncing = true;
-                                    let mut addr = String::new();
+                                    //the announcing relay is the first one for downloading blockchain.zip, the other dialed relays are mirrors
+                                    sync_addrs.clear();
                                     for add in dialed_addr.clone() {
                                         if add.contains(&propagation_source.to_string()) {
-                                            addr = add.clone();
-                                            break;
+                                            sync_addrs.insert(0, add);
+                                        } else {
+                                            sync_addrs.push(add);
                                         }
                                     }
 
-                                    match syncing(addr.clone()).await {
-                                        Ok(_) => {
-                                            write_log("syncing completed");
-                                            let fullnodes_req = Req {
-                                                req: "fullnodes".to_string(),
-                                            };
-                                            swarm
-                                                .behaviour_mut()
-                                                .req_res
-                                                .send_request(&propagation_source, fullnodes_req);
-                                        }
-                                        Err(_) => {
-                                            write_log("syncing error in get gossip(line 283)");
+                                    //get blocks above our last block, or the whole blockchain.zip if snapshot syncing is enabled
+                                    if !block_sync
+                                        .start(&mut swarm, propagation_source, relays)
+                                        .await
+                                    {
+                                        if CONFIG.sync_mode == SyncMode::Snapshot {
+                                            snapshot_sync = Some(SnapshotSync {
+                                                source: propagation_source,
+                                                task: tokio::spawn(syncing(sync_addrs.clone())),
+                                            });
+                                        } else {
+                                            write_log("block syncing could not start");
                                             in_syncing = false;
                                         }
                                     }
@@ -354,11 +574,15 @@ async fn handle_new_swarm_events(
                     _ => (),
                 },
                 CustomBehavEvent::ReqRes(req_res) => match req_res {
-                    Event::Message { message, .. } => match message {
+                    Event::Message { peer, message } => match message {
                         libp2p::request_response::Message::Request {
                             channel, request, ..
                         } => {
                             if *sync {
+                                //peers that have done the handshake can reserve circuits
+                                if request.req == "handshake" {
+                                    handshake_done(peer);
+                                }
                                 handle_requests(
                                     request,
                                     &mut swarm,
@@ -366,88 +590,55 @@ async fn handle_new_swarm_events(
                                     wallet,
                                     fullnodes,
                                     leader,
-                                    clients_topic.clone(),
-                                    relays,
-                                    clients,
-                                    relay_topic.clone(),
-                                    local_peer_id,
-                                )
-                                .await;
+                                    &mut pipeline,
+                                );
                             }
                         }
-                        libp2p::request_response::Message::Response { response, .. } => {
-                            if let Ok(fullnode_subs) =
+                        libp2p::request_response::Message::Response {
+                            request_id,
+                            response,
+                        } => {
+                            let progress = block_sync.handle_response(
+                                request_id,
+                                response.clone(),
+                                &mut swarm,
+                            );
+                            if progress != SyncProgress::NotMine {
+                                finish_block_sync(
+                                    progress,
+                                    &mut block_sync,
+                                    &mut swarm,
+                                    &sync_addrs,
+                                    &mut in_syncing,
+                                    &mut snapshot_sync,
+                                );
+                            } else if let Ok(fullnode_subs) =
                                 serde_json::from_str::<Vec<FullNodes>>(&response.res)
                             {
-                                let mut set_sync = true;
-                                for fullnode in fullnode_subs.clone() {
+                                for fullnode in fullnode_subs {
                                     fullnodes.push(fullnode)
                                 }
-                                if syncing_blocks.len() > 0 {
-                                    for gossipmsg in syncing_blocks.clone() {
-                                        let str_msg =
-                                            &serde_json::to_string(&gossipmsg.gossip).unwrap();
-                                        match verifying_block(
-                                            str_msg,
-                                            leader,
-                                            &mut fullnode_subs.clone(),
-                                        )
-                                        .await
-                                        {
-                                            Ok(_) => {
-                                                write_log("verifying block before syncing");
-                                            }
-                                            Err(e) => {
-                                                if e != "reject" {
-                                                    set_sync = false;
-                                                    write_log("verifying block error in syncing blocks of handle events(line 351)");
-                                                    write_log(&format!(
-                                                        "block insert error: {}",
-                                                        e
-                                                    ));
-                                                    //remove node from fullnodes list because its block is wrong!
-                                                    let index = fullnodes.iter().position(|node| {
-                                                        node.peer_id
-                                                            == gossipmsg
-                                                                .gossip
-                                                                .block
-                                                                .header
-                                                                .validator
-                                                                .parse()
-                                                                .unwrap()
-                                                    });
-                                                    match index {
-                                                        Some(i) => {
-                                                            fullnodes.remove(i);
-                                                        }
-                                                        None => {}
-                                                    }
-                                                }
-                                            }
-                                        }
+                                //gossip blocks that came while syncing are stored by the pipeline
+                                let mut queued = 0;
+                                for gossipmsg in syncing_blocks.drain(..) {
+                                    let job = BlockJob {
+                                        str_msg: serde_json::to_string(&gossipmsg.gossip).unwrap(),
+                                        gossip_message: gossipmsg.gossip,
+                                        origin: BlockOrigin::Syncing,
+                                    };
+                                    if pipeline.push_block(job, leader, fullnodes) {
+                                        queued += 1;
+                                    } else {
+                                        syncing_failed = true;
                                     }
                                 }
-
-                                if set_sync {
-                                    *sync = true;
-                                    send_addr_to_server(my_addresses[0].clone()).await;
-                                    let my_multiaddress: Multiaddr =
-                                        my_addresses[0].parse().unwrap();
-                                    let str_my_multiaddr =
-                                        serde_json::to_string(&my_multiaddress).unwrap();
-                                    match swarm
-                                        .behaviour_mut()
-                                        .gossipsub
-                                        .publish(clients_topic.clone(), str_my_multiaddr.as_bytes())
-                                    {
-                                        Ok(_) => write_log("my address propagate to the network"),
-                                        Err(_) => write_log(
-                                            "my address propagation error! handle_events(line 380)",
-                                        ),
-                                    }
+                                if queued > 0 {
+                                    syncing_left = Some(queued);
+                                } else if !syncing_failed {
+                                    synced(&mut swarm, my_addresses, &clients_topic, sync).await;
                                 } else {
                                     for connected in connections.clone() {
-                                        swarm.disconnect_peer_id(connected.clone()).unwrap();
+                                        let _ = swarm.disconnect_peer_id(connected);
                                     }
                                     leader.clear();
                                     fullnodes.clear();
@@ -465,10 +656,99 @@ async fn handle_new_swarm_events(
                             }
                         }
                     },
+                    Event::OutboundFailure { request_id, .. } => {
+                        let progress = block_sync.handle_failure(request_id, &mut swarm);
+                        finish_block_sync(
+                            progress,
+                            &mut block_sync,
+                            &mut swarm,
+                            &sync_addrs,
+                            &mut in_syncing,
+                            &mut snapshot_sync,
+                        );
+                    }
                     _ => (),
                 },
+                CustomBehavEvent::Kademlia(kad_event) => {
+                    handle_kademlia_event(&mut swarm, kad_event)
+                }
+                CustomBehavEvent::Mdns(libp2p::mdns::Event::Discovered(found)) => {
+                    handle_mdns_event(&mut swarm, found)
+                }
+                CustomBehavEvent::Identify(identify_event) => {
+                    handle_identify_event(&mut swarm, identify_event)
+                }
+                CustomBehavEvent::Relay(relay_event) => handle_relay_event(relay_event),
+                CustomBehavEvent::Dcutr(dcutr_event) => handle_dcutr_event(dcutr_event),
+                CustomBehavEvent::Autonat(libp2p::autonat::Event::StatusChanged { old, new }) => {
+                    write_log(&format!("reachability changed: {:?} -> {:?}", old, new));
+                    set_nat_status(&new);
+                }
+                _ => (),
             },
             _ => (),
         }
     }
 }
+
+//the relay is synced, announce its address to the network
+async fn synced(
+    swarm: &mut Swarm<CustomBehav>,
+    my_addresses: &[String],
+    clients_topic: &IdentTopic,
+    sync: &mut bool,
+) {
+    *sync = true;
+    announce_address(swarm, &my_addresses[0].clone()).await;
+    let my_multiaddress: Multiaddr = my_addresses[0].parse().unwrap();
+    let str_my_multiaddr = serde_json::to_string(&my_multiaddress).unwrap();
+    match swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(clients_topic.clone(), str_my_multiaddr.as_bytes())
+    {
+        Ok(_) => write_log("my address propagate to the network"),
+        Err(_) => write_log("my address propagation error! handle_events(line 380)"),
+    }
+}
+
+//get fullnodes after block syncing or get the whole blockchain.zip if block syncing failed in snapshot mode
+fn finish_block_sync(
+    progress: SyncProgress,
+    block_sync: &mut BlockSync,
+    swarm: &mut Swarm<CustomBehav>,
+    sync_addrs: &[String],
+    in_syncing: &mut bool,
+    snapshot_sync: &mut Option<SnapshotSync>,
+) {
+    let source = match block_sync.source {
+        Some(source) => source,
+        None => return,
+    };
+    match progress {
+        SyncProgress::Done => {
+            block_sync.reset();
+            let fullnodes_req = Req {
+                req: "fullnodes".to_string(),
+            };
+            swarm
+                .behaviour_mut()
+                .req_res
+                .send_request(&source, fullnodes_req);
+        }
+        SyncProgress::Failed if CONFIG.sync_mode != SyncMode::Snapshot => {
+            write_log("block syncing failed");
+            block_sync.reset();
+            *in_syncing = false;
+        }
+        SyncProgress::Failed => {
+            write_log("block syncing failed, syncing with the whole blockchain");
+            block_sync.reset();
+            *snapshot_sync = Some(SnapshotSync {
+                source,
+                task: tokio::spawn(syncing(sync_addrs.to_vec())),
+            });
+        }
+        _ => {}
+    }
+}
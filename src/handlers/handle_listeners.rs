@@ -20,6 +20,16 @@ pub async fn handle(address: Multiaddr, local_peer_id: PeerId, my_addresses: &mu
     my_addresses.push(my_full_addr.clone());
 }
 
+//external address that autonat has confirmed is the first address of relay
+pub fn handle_confirmed(address: Multiaddr, local_peer_id: PeerId, my_addresses: &mut Vec<String>) {
+    let my_full_addr = format!("{}/p2p/{}", address, local_peer_id);
+    if let Err(e) = fs::write("/etc/myaddress.dat", my_full_addr.clone()) {
+        write_log(&format!("writing myaddress.dat problem: {}", e));
+    }
+    my_addresses.retain(|addr| addr != &my_full_addr);
+    my_addresses.insert(0, my_full_addr);
+}
+
 pub async fn send_addr_to_server(full_addr: String) {
     let os = std::env::consts::OS;
     let mut path = "";
@@ -1,37 +1,175 @@
 use libp2p::{
-    gossipsub::{IdentTopic, Message},
+    gossipsub::{IdentTopic, Message, MessageAcceptance, MessageId},
     PeerId, Swarm,
 };
 
 use super::{
-    check_trx::handle_transactions,
+    ban_list::ban_peer,
+    chain_spec::CHAIN_SPEC,
+    config::CONFIG,
+    create_log::write_log,
     nodes_sync_announce::handle_sync_message,
-    recieved_block::verifying_block,
-    structures::{FullNodes, GossipMessage},
-    CustomBehav
+    outnodes::handle_outnode,
+    recieved_block::next_round,
+    storage_pipeline::{
+        BlockJob, BlockOrigin, BlockResult, GossipSource, StoragePipeline, TransactionOrigin,
+        TransactionResult,
+    },
+    structures::{FullNodes, GossipMessage, Res, Transaction},
+    CustomBehav,
 };
 
-//check gossip messages and do its operations.....................................................................
-pub async fn msg_check(
+//check gossip messages and put their storage work in the pipeline...................................................
+//blocks and transactions are forwarded by gossipsub after their validation, other messages at once
+pub fn msg_check(
     message: Message,
-    mut leader: &mut String,
-    fullnodes: &mut Vec<FullNodes>,
-    relays: &mut Vec<PeerId>,
+    message_id: MessageId,
     propagation_source: PeerId,
+    leader: &str,
+    fullnodes: &mut Vec<FullNodes>,
     swarm: &mut Swarm<CustomBehav>,
-    connections: &mut Vec<PeerId>,
-    local_peer_id: PeerId,
+    pipeline: &mut StoragePipeline,
 ) {
     let str_msg = String::from_utf8(message.data.clone()).unwrap();
+    let source = GossipSource {
+        message_id,
+        propagation_source,
+    };
 
     handle_sync_message(fullnodes, &str_msg);
 
-    handle_transactions(String::from_utf8(message.data).unwrap()).await;
+    let queued = if serde_json::from_str::<Transaction>(&str_msg).is_ok() {
+        pipeline
+            .push_transaction(str_msg, TransactionOrigin::Gossip(source.clone()))
+            .is_ok()
+    } else if let Ok(gossip_message) = serde_json::from_str::<GossipMessage>(&str_msg) {
+        let job = BlockJob {
+            str_msg,
+            gossip_message,
+            origin: BlockOrigin::Gossip(source.clone()),
+        };
+        pipeline.push_block(job, leader, fullnodes)
+    } else {
+        report_validation(swarm, &source, &Ok(()));
+        true
+    };
+    //queues are full
+    if !queued {
+        report_validation(swarm, &source, &Err("reject"));
+    }
+}
+
+//report the result of a gossip message to gossipsub
+//Ok: forward it, Err("reject"): don't forward it (duplicate, busy, can't be checked now or not for
+//our tip and round), other errors (wrong signature, hash or structure): don't forward it and
+//penalize the peer that sent it
+pub fn report_validation(
+    swarm: &mut Swarm<CustomBehav>,
+    source: &GossipSource,
+    result: &Result<(), &str>,
+) {
+    let acceptance = match result {
+        Ok(_) => MessageAcceptance::Accept,
+        Err(e) if *e == "reject" => MessageAcceptance::Ignore,
+        Err(_) => MessageAcceptance::Reject,
+    };
+    let _ = swarm
+        .behaviour_mut()
+        .gossipsub
+        .report_message_validation_result(
+            &source.message_id,
+            &source.propagation_source,
+            acceptance,
+        );
+}
+
+//report a gossip transaction or publish a transaction of a client after it is checked
+pub fn handle_transaction_result(tx_result: TransactionResult, swarm: &mut Swarm<CustomBehav>) {
+    let TransactionResult {
+        message,
+        origin,
+        result,
+    } = tx_result;
+    let channel = match origin {
+        TransactionOrigin::Gossip(source) => {
+            report_validation(swarm, &source, &result);
+            return;
+        }
+        TransactionOrigin::Request { channel } => channel,
+    };
+    let res = match result {
+        Ok(_) => {
+            //send true transaction to sse servers
+            let _ = swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(CHAIN_SPEC.topic("sse"), message.as_bytes());
+
+            //send true transaction to connected Validators and relays
+            match swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(CHAIN_SPEC.topic("client"), message.as_bytes())
+            {
+                Ok(_) => "Your transaction sent.".to_string(),
+                Err(_) => "sending error!".to_string(),
+            }
+        }
+        Err(e) => format!("transaction is not accepted: {}", e),
+    };
+    let _ = swarm
+        .behaviour_mut()
+        .req_res
+        .send_response(channel, Res { res });
+}
 
-    match verifying_block(&str_msg, &mut leader, fullnodes).await {
+//set the next round and publish a stored block or ban the peer of a wrong block
+pub async fn handle_block_result(
+    block_result: BlockResult,
+    swarm: &mut Swarm<CustomBehav>,
+    leader: &mut String,
+    fullnodes: &mut Vec<FullNodes>,
+    relays: &mut Vec<PeerId>,
+    clients: &mut Vec<PeerId>,
+    connections: &[PeerId],
+) {
+    let relay_topic = CHAIN_SPEC.topic("relay");
+    let local_peer_id = *swarm.local_peer_id();
+    let BlockResult { job, result } = block_result;
+    let str_msg = job.str_msg;
+    if let BlockOrigin::Gossip(source) = &job.origin {
+        report_validation(swarm, source, &result);
+    }
+    match result {
         Ok(_) => {
+            next_round(&job.gossip_message, leader, fullnodes);
+            //blocks that came while syncing are forwarded by other relays already
+            if let BlockOrigin::Syncing = job.origin {
+                return;
+            }
+
+            //send blocks of validators to other relays and answer the validator
+            if let BlockOrigin::Request { channel } = job.origin {
+                match swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .publish(relay_topic, str_msg.as_bytes())
+                {
+                    Ok(_) => {
+                        let response = Res { res: String::new() };
+                        let _ = swarm
+                            .behaviour_mut()
+                            .req_res
+                            .send_response(channel, response);
+                    }
+                    Err(_) => {
+                        // write_log("sending gossip message to relay topic has problem");
+                    }
+                }
+            }
+
             //send true block to sse servers
-            let sse_topic = IdentTopic::new("sse");
+            let sse_topic = CHAIN_SPEC.topic("sse");
             match swarm
                 .behaviour_mut()
                 .gossipsub
@@ -54,26 +192,63 @@ pub async fn msg_check(
         }
         Err(e) => {
             if e != "reject" {
-                let gossipmsg: GossipMessage = serde_json::from_str(&str_msg).unwrap();
-                let c_index = fullnodes.iter().position(|node| {
-                    node.peer_id == gossipmsg.block.header.validator.parse().unwrap()
-                });
-                match c_index {
-                    Some(i) => {
-                        fullnodes.remove(i);
-                    }
-                    None => {}
-                }
+                write_log(&format!("wrong block: {}", e));
+                //validator of a wrong block may not be a peer id
+                let validator = job
+                    .gossip_message
+                    .block
+                    .header
+                    .validator
+                    .parse::<PeerId>()
+                    .ok();
+                match job.origin {
+                    BlockOrigin::Gossip(GossipSource {
+                        propagation_source, ..
+                    }) => {
+                        fullnodes.retain(|node| Some(node.peer_id) != validator);
 
-                let r_index = relays.iter().position(|relay| relay == &propagation_source);
-                match r_index {
-                    Some(i) => {
-                        relays.remove(i);
-                        if connections.contains(&propagation_source) {
-                            swarm.disconnect_peer_id(propagation_source).unwrap();
+                        let r_index = relays.iter().position(|relay| relay == &propagation_source);
+                        match r_index {
+                            Some(i) => {
+                                relays.remove(i);
+                                if connections.contains(&propagation_source) {
+                                    ban_peer(
+                                        swarm,
+                                        propagation_source,
+                                        CONFIG.peer_scoring.ban_minutes,
+                                        &format!("wrong block: {}", e),
+                                    );
+                                }
+                            }
+                            None => {}
                         }
                     }
-                    None => {}
+                    BlockOrigin::Request { .. } => {
+                        let validator = match validator {
+                            Some(validator) => validator,
+                            None => return,
+                        };
+                        handle_outnode(
+                            validator,
+                            swarm,
+                            CHAIN_SPEC.topic("client"),
+                            relays,
+                            clients,
+                            relay_topic,
+                            fullnodes,
+                        )
+                        .await;
+                        ban_peer(
+                            swarm,
+                            validator,
+                            CONFIG.peer_scoring.ban_minutes,
+                            &format!("wrong block: {}", e),
+                        );
+                    }
+                    //remove node from fullnodes list because its block is wrong
+                    BlockOrigin::Syncing => {
+                        fullnodes.retain(|node| Some(node.peer_id) != validator);
+                    }
                 }
             }
         }
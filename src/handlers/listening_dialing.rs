@@ -1,9 +1,9 @@
-use std::{env::consts::OS, fs::{self, File, OpenOptions}, io::{BufRead, BufReader, BufWriter, Write}, net::TcpStream, sync::{Arc, Mutex}};
+use std::{fs::{self, OpenOptions}, io::{BufWriter, Write}, net::TcpStream, sync::{Arc, Mutex}};
 
-use libp2p::{gossipsub::IdentTopic, Multiaddr, PeerId, Swarm};
+use libp2p::{gossipsub::IdentTopic, PeerId, Swarm};
 use rand::seq::SliceRandom;
 
-use super::{create_log::write_log, handle_events::events, handle_listeners::send_addr_to_server, structures::{FullNodes, GetGossipMsg}, swarm_config::CustomBehav, Addresses};
+use super::{ban_list::is_banned_addr, config::CONFIG, create_log::write_log, discovery::{announce_address, known_relays, relays_path, seed_kademlia}, handle_events::events, structures::{FullNodes, GetGossipMsg}, swarm_config::CustomBehav, Addresses};
 
 pub async fn start(
     local_peer_id: PeerId,
@@ -23,30 +23,18 @@ pub async fn start(
     syncing_blocks: &mut Vec<GetGossipMsg>,
 ) {
     loop {
-        let server_address = "www.centichain.org:80";
-        let site_connection = TcpStream::connect(server_address);
-
-        let mut relays_path = "";
-        if OS == "windows" {
-            relays_path = "relays.dat"
-        } else if OS == "linux" {
-            relays_path = "/etc/relays.dat"
-        }
-
-        match site_connection {
-            Ok(_) => get_addresses(relays_path).await,
-            Err(_) => write_log(
-                "Relay could not connect to centichain.org for get latest relays addresses! mod.rs(line 73)",
-            ),
+        //the web registry is optional, kademlia finds relays without it
+        if CONFIG.discovery.web_registry {
+            let server_address = "www.centichain.org:80";
+            match TcpStream::connect(server_address) {
+                Ok(_) => get_addresses(relays_path()).await,
+                Err(_) => write_log(
+                    "Relay could not connect to centichain.org for get latest relays addresses! mod.rs(line 73)",
+                ),
+            }
         }
-        let mut dialed_addr = dialing(
-            relays_path,
-            local_peer_id,
-            Arc::clone(&swarm),
-            sync,
-            my_addresses,
-        )
-        .await;
+        seed_kademlia(&mut swarm.lock().unwrap());
+        let mut dialed_addr = dialing(local_peer_id, Arc::clone(&swarm), sync, my_addresses).await;
         let mut im_first = false;
         if dialed_addr.len() == 0 {
             im_first = true;
@@ -115,75 +103,59 @@ async fn get_addresses(relays_path: &str) {
 }
 
 pub async fn dialing(
-    relays_path: &str,
     local_peer_id: PeerId,
     swarm: Arc<Mutex<Swarm<CustomBehav>>>,
     sync: &mut bool,
     my_addresses: &mut Vec<String>,
 ) -> Vec<String> {
     let mut swarm = swarm.lock().unwrap();
-    let relays_file_exist = fs::metadata(relays_path).is_ok();
     let mut dialed_addr: Vec<String> = Vec::new();
-    if relays_file_exist {
-        let file = File::open(relays_path).unwrap();
-        let reader = BufReader::new(&file);
-        let mut dial_addresses = Vec::new();
-        for i in reader.lines() {
-            let addr = i.unwrap();
-            if addr.trim().len() > 0 {
-                match addr.parse::<Multiaddr>() {
-                    Ok(addresses) => {
-                        if !addresses.to_string().contains(&local_peer_id.to_string()) {
-                            dial_addresses.push(addresses);
-                        }
-                    }
-                    Err(_) => {}
-                }
-            }
+    //relays of relays.dat and bootstrap peers
+    let mut dial_addresses = Vec::new();
+    for addresses in known_relays() {
+        if is_banned_addr(&addresses) {
+            write_log(&format!("banned address is not dialed: {}", addresses));
+        } else if !addresses.to_string().contains(&local_peer_id.to_string()) {
+            dial_addresses.push(addresses);
         }
+    }
 
-        if dial_addresses.len() > 0 {
-            if dial_addresses.len() < 6 {
-                for addr in dial_addresses {
-                    match swarm.dial(addr.clone()) {
-                        Ok(_) => {
-                            write_log(&format!("dialing with: {}", addr));
-                            dialed_addr.push(addr.to_string());
-                        }
-                        Err(_) => {
-                            write_log(&format!("dialing problem with: {}", addr));
-                        }
-                    }
-                }
-            } else {
-                let mut rnd_relays = Vec::new();
-                while rnd_relays.len() < 6 {
-                    let new_rnd = dial_addresses.choose(&mut rand::thread_rng()).unwrap();
-                    if !rnd_relays.contains(new_rnd) {
-                        rnd_relays.push(new_rnd.clone())
+    if dial_addresses.len() > 0 {
+        if dial_addresses.len() < 6 {
+            for addr in dial_addresses {
+                match swarm.dial(addr.clone()) {
+                    Ok(_) => {
+                        write_log(&format!("dialing with: {}", addr));
+                        dialed_addr.push(addr.to_string());
                     }
-                }
-                for addr in rnd_relays {
-                    match swarm.dial(addr.clone()) {
-                        Ok(_) => {
-                            write_log(&format!("dialing with: {}", addr));
-                            dialed_addr.push(addr.to_string());
-                        }
-                        Err(_) => {
-                            write_log(&format!("dialing problem with: {}", addr));
-                        }
+                    Err(_) => {
+                        write_log(&format!("dialing problem with: {}", addr));
                     }
                 }
             }
         } else {
-            if my_addresses.len() > 0 {
-                send_addr_to_server(my_addresses[0].clone()).await;
+            let mut rnd_relays = Vec::new();
+            while rnd_relays.len() < 6 {
+                let new_rnd = dial_addresses.choose(&mut rand::thread_rng()).unwrap();
+                if !rnd_relays.contains(new_rnd) {
+                    rnd_relays.push(new_rnd.clone())
+                }
+            }
+            for addr in rnd_relays {
+                match swarm.dial(addr.clone()) {
+                    Ok(_) => {
+                        write_log(&format!("dialing with: {}", addr));
+                        dialed_addr.push(addr.to_string());
+                    }
+                    Err(_) => {
+                        write_log(&format!("dialing problem with: {}", addr));
+                    }
+                }
             }
-            *sync = true
         }
     } else {
         if my_addresses.len() > 0 {
-            send_addr_to_server(my_addresses[0].clone()).await;
+            announce_address(&mut swarm, &my_addresses[0].clone()).await;
         }
         *sync = true
     }
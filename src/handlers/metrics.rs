@@ -0,0 +1,78 @@
+// Metrics of the relay, served by the /metrics RPC
+
+use std::{sync::Mutex, time::Duration};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+//time of writing changes of blocks to the database
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ApplyLatency {
+    pub blocks: u64,
+    pub last_block: Option<i64>,
+    pub last_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    #[serde(skip)]
+    total_ms: f64,
+}
+
+//reservations and circuits of the circuit relay server
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct CircuitRelayStats {
+    pub active_reservations: usize,
+    pub active_circuits: usize,
+    pub reservations_accepted: u64,
+    pub reservations_denied: u64,
+    pub circuits_accepted: u64,
+    pub circuits_denied: u64,
+}
+
+//upgrades of relayed connections to direct connections (DCUtR)
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct HolePunchStats {
+    pub successes: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Metrics {
+    pub block_apply: ApplyLatency,
+    pub circuit_relay: CircuitRelayStats,
+    pub hole_punch: HolePunchStats,
+}
+
+pub static METRICS: Lazy<Mutex<Metrics>> = Lazy::new(|| Mutex::new(Metrics::default()));
+
+pub fn record_block_apply(number: i64, elapsed: Duration) {
+    let ms = elapsed.as_secs_f64() * 1000.0;
+    let mut metrics = METRICS.lock().unwrap();
+    let apply = &mut metrics.block_apply;
+    apply.blocks += 1;
+    apply.last_block = Some(number);
+    apply.last_ms = ms;
+    apply.total_ms += ms;
+    apply.avg_ms = apply.total_ms / apply.blocks as f64;
+    apply.max_ms = apply.max_ms.max(ms);
+}
+
+pub fn update_circuit_relay(update: impl FnOnce(&mut CircuitRelayStats)) {
+    update(&mut METRICS.lock().unwrap().circuit_relay);
+}
+
+pub fn record_hole_punch(result: Result<(), String>) {
+    let mut metrics = METRICS.lock().unwrap();
+    let hole_punch = &mut metrics.hole_punch;
+    match result {
+        Ok(_) => hole_punch.successes += 1,
+        Err(e) => {
+            hole_punch.failures += 1;
+            hole_punch.last_error = Some(e);
+        }
+    }
+}
+
+pub fn metrics() -> Metrics {
+    METRICS.lock().unwrap().clone()
+}
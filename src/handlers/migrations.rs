@@ -0,0 +1,206 @@
+// Schema version of the database is kept in the "schema" collection as {_id: "version", version}.
+// At startup the relay creates the indexes of all collections and applies the migrations that are
+// newer than the recorded version, one by one and in order. After each migration the new version
+// is recorded, so an interrupted migration runs again at the next startup (migrations must be safe
+// to run twice).
+//
+// A block that is stored but can't be applied to UTXOs and reciepts is recorded in the same
+// collection as {_id: "reindex", block_number}; the relay doesn't start until reindex removes it.
+//
+// When a document layout in structures.rs changes, add a migration to `migrate` and increase
+// SCHEMA_VERSION.
+//
+// Versions:
+//   0 - layout of the first relays (no schema record)
+//   1 - one UTXOs document per unspent output (UtxoRecord) instead of one per public key
+
+use mongodb::{
+    bson::{doc, Document},
+    options::{IndexOptions, UpdateOptions},
+    Collection, Database, IndexModel,
+};
+
+use super::{
+    create_log::write_log, db_connection::blockchain_db, utxo_store::convert_legacy_utxos,
+};
+
+pub const SCHEMA_VERSION: i64 = 1;
+
+//create indexes and migrate the database to SCHEMA_VERSION
+pub async fn migrate_database() -> Result<(), String> {
+    let db = blockchain_db().await?;
+    let mut version = schema_version(&db).await?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "database schema version {} is newer than this relay ({})",
+            version, SCHEMA_VERSION
+        ));
+    }
+
+    while version < SCHEMA_VERSION {
+        migrate(&db, version + 1).await?;
+        version += 1;
+        set_schema_version(&db, version).await?;
+        write_log(&format!("database migrated to schema version {}", version));
+    }
+
+    create_indexes(&db).await
+}
+
+async fn schema_version(db: &Database) -> Result<i64, String> {
+    let schema_coll: Collection<Document> = db.collection("schema");
+    match schema_coll.find_one(doc! {"_id": "version"}, None).await {
+        Ok(Some(doc)) => doc.get_i64("version").map_err(|e| e.to_string()),
+        Ok(None) => Ok(0),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn set_schema_version(db: &Database, version: i64) -> Result<(), String> {
+    let schema_coll: Collection<Document> = db.collection("schema");
+    let option = UpdateOptions::builder().upsert(true).build();
+    match schema_coll
+        .update_one(
+            doc! {"_id": "version"},
+            doc! {"$set": {"version": version}},
+            option,
+        )
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+//record that UTXOs and reciepts are not made from all of the stored blocks
+pub async fn mark_for_reindex(db: &Database, block_number: i64) -> Result<(), String> {
+    let schema_coll: Collection<Document> = db.collection("schema");
+    let option = UpdateOptions::builder().upsert(true).build();
+    match schema_coll
+        .update_one(
+            doc! {"_id": "reindex"},
+            doc! {"$set": {"block_number": block_number}},
+            option,
+        )
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+//number of the block that is not applied if the database needs reindex
+async fn reindex_mark(db: &Database) -> Result<Option<i64>, String> {
+    let schema_coll: Collection<Document> = db.collection("schema");
+    match schema_coll.find_one(doc! {"_id": "reindex"}, None).await {
+        Ok(Some(doc)) => doc
+            .get_i64("block_number")
+            .map(Some)
+            .map_err(|e| e.to_string()),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+//error if the database needs reindex, the relay can't work with it
+pub async fn check_reindex_mark() -> Result<(), String> {
+    let db = blockchain_db().await?;
+    match reindex_mark(&db).await? {
+        Some(number) => Err(format!(
+            "block {} is not fully applied, run reindex before starting the relay",
+            number
+        )),
+        None => Ok(()),
+    }
+}
+
+pub async fn clear_reindex_mark(db: &Database) -> Result<(), String> {
+    let schema_coll: Collection<Document> = db.collection("schema");
+    match schema_coll.delete_one(doc! {"_id": "reindex"}, None).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+//change documents from the layout of version - 1 to the layout of version
+async fn migrate(db: &Database, version: i64) -> Result<(), String> {
+    match version {
+        1 => {
+            let utxos_coll: Collection<Document> = db.collection("UTXOs");
+            match convert_legacy_utxos(&utxos_coll).await {
+                Ok(converted) => {
+                    write_log(&format!(
+                        "UTXOs of {} public keys converted to per output records",
+                        converted
+                    ));
+                    Ok(())
+                }
+                Err(_) => Err("converting old UTXOs problem".to_string()),
+            }
+        }
+        _ => Err(format!("there is not any migration to version {}", version)),
+    }
+}
+
+fn index(keys: Document, name: &str, unique: bool) -> IndexModel {
+    let options = IndexOptions::builder()
+        .name(name.to_string())
+        .unique(unique)
+        .build();
+    IndexModel::builder().keys(keys).options(options).build()
+}
+
+//creating an index that already exists doesn't change anything
+async fn create_indexes(db: &Database) -> Result<(), String> {
+    let blocks_coll: Collection<Document> = db.collection("Blocks");
+    let utxos_coll: Collection<Document> = db.collection("UTXOs");
+    let reciept_coll: Collection<Document> = db.collection("reciept");
+    let headers_coll: Collection<Document> = db.collection("Headers");
+
+    //a transaction can be only in one block, blocks without transactions are not in this index
+    let tx_hash_options = IndexOptions::builder()
+        .name("tx_hash".to_string())
+        .unique(true)
+        .partial_filter_expression(doc! {"body.transactions.tx_hash": {"$exists": true}})
+        .build();
+    let blocks_indexes = vec![
+        index(doc! {"header.blockhash": 1}, "blockhash", true),
+        index(doc! {"header.number": 1}, "number", true),
+        IndexModel::builder()
+            .keys(doc! {"body.transactions.tx_hash": 1})
+            .options(tx_hash_options)
+            .build(),
+    ];
+    //headers of pruned blocks
+    let headers_indexes = vec![
+        index(doc! {"header.blockhash": 1}, "blockhash", true),
+        index(doc! {"header.number": 1}, "number", true),
+    ];
+    //coinbase has one reciept for each output with the same hash
+    let reciept_indexes = vec![
+        index(doc! {"hash": 1}, "hash", false),
+        index(doc! {"to": 1}, "to", false),
+        index(doc! {"from": 1}, "from", false),
+        index(doc! {"block_number": 1}, "block_number", false),
+    ];
+    let utxos_indexes = vec![
+        index(doc! {"public_key": 1}, "owner", false),
+        index(doc! {"block_number": 1}, "block_number", false),
+    ];
+
+    for (coll, indexes) in [
+        (blocks_coll, blocks_indexes),
+        (reciept_coll, reciept_indexes),
+        (utxos_coll, utxos_indexes),
+        (headers_coll, headers_indexes),
+    ] {
+        if let Err(e) = coll.create_indexes(indexes, None).await {
+            return Err(format!(
+                "creating indexes of {} problem: {}",
+                coll.name(),
+                e
+            ));
+        }
+    }
+    Ok(())
+}
@@ -1,21 +1,38 @@
+pub mod ban_list;
+pub mod block_apply;
+pub mod block_sync;
+pub mod chain_spec;
+mod circuit_relay;
+mod discovery;
+mod download;
 mod gossip_messages;
 pub mod handle_events;
 mod handle_listeners;
 mod outnodes;
+mod peer_scoring;
+pub mod pruning;
 mod remove_relays;
 mod requests;
 mod send_address;
+pub mod snapshot;
+pub mod snapshot_scheduler;
+mod storage_pipeline;
 pub mod structures;
 pub mod check_trx;
+pub mod config;
 pub mod create_log;
 pub mod db_connection;
 mod get_addresses;
 mod handle_messages;
+pub mod metrics;
+pub mod migrations;
 mod nodes_sync_announce;
+pub mod reachability;
 mod reciept;
-mod recieved_block;
+pub mod recieved_block;
 mod syncing;
 pub mod swarm_config;
+pub mod utxo_store;
 use swarm_config::CustomBehav;
 pub mod run_relay;
 pub mod listening_dialing;
@@ -0,0 +1,125 @@
+// Gossipsub peer scoring
+//
+// Every topic has its own score parameters. Relay and client topics carry blocks and transactions,
+// so invalid messages (rejected by the validation of the storage pipeline) in them cost the most;
+// sse and validator topics only carry blocks that relays have accepted before. Deliveries in the
+// mesh are not scored because blocks are not made at a fixed rate and quiet topics would penalize
+// honest peers. Peers below the thresholds of the config lose gossip, publishing and then all of
+// their messages (graylist), and peers below the ban threshold are banned for ban_minutes.
+// Many peers on one IP address are penalized too, except bootstrap peers of the config.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::Duration,
+};
+
+use libp2p::{
+    gossipsub::{IdentTopic, PeerScoreParams, PeerScoreThresholds, TopicHash, TopicScoreParams},
+    multiaddr::Protocol,
+    Multiaddr, PeerId, Swarm,
+};
+
+use super::{
+    ban_list::{ban_peer, lift_expired_bans},
+    chain_spec::CHAIN_SPEC,
+    config::CONFIG,
+    swarm_config::CustomBehav,
+};
+
+pub fn score_params(local_peer_id: &PeerId) -> (PeerScoreParams, PeerScoreThresholds) {
+    let scoring = &CONFIG.peer_scoring;
+    let mut topics: HashMap<TopicHash, TopicScoreParams> = HashMap::new();
+    topics.insert(
+        CHAIN_SPEC.topic("relay").hash(),
+        topic_params(1.0, scoring.invalid_message_weight),
+    );
+    topics.insert(
+        CHAIN_SPEC.topic("client").hash(),
+        topic_params(1.0, scoring.invalid_message_weight),
+    );
+    topics.insert(
+        CHAIN_SPEC.topic("sse").hash(),
+        topic_params(0.2, scoring.invalid_message_weight),
+    );
+    //topic of validators that are connected to this relay
+    topics.insert(
+        IdentTopic::new(local_peer_id.to_string()).hash(),
+        topic_params(0.2, scoring.invalid_message_weight),
+    );
+
+    let params = PeerScoreParams {
+        topics,
+        ip_colocation_factor_weight: CONFIG.ip_colocation_weight(),
+        ip_colocation_factor_threshold: scoring.ip_colocation_threshold,
+        ip_colocation_factor_whitelist: bootstrap_ips(),
+        ..Default::default()
+    };
+    let thresholds = PeerScoreThresholds {
+        gossip_threshold: scoring.gossip_threshold,
+        publish_threshold: scoring.publish_threshold,
+        graylist_threshold: scoring.graylist_threshold,
+        ..Default::default()
+    };
+    (params, thresholds)
+}
+
+//IP addresses of bootstrap peers of the config
+fn bootstrap_ips() -> HashSet<IpAddr> {
+    CONFIG
+        .discovery
+        .bootstrap_peers
+        .iter()
+        .filter_map(|peer| peer.parse::<Multiaddr>().ok())
+        .filter_map(|addr| {
+            addr.iter().find_map(|protocol| match protocol {
+                Protocol::Ip4(ip4) => Some(IpAddr::V4(ip4)),
+                Protocol::Ip6(ip6) => Some(IpAddr::V6(ip6)),
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+fn topic_params(topic_weight: f64, invalid_weight: f64) -> TopicScoreParams {
+    TopicScoreParams {
+        topic_weight,
+        //small reward for staying in the mesh (max 36 after an hour)
+        time_in_mesh_weight: 0.01,
+        time_in_mesh_quantum: Duration::from_secs(1),
+        time_in_mesh_cap: 3600.0,
+        first_message_deliveries_weight: 1.0,
+        first_message_deliveries_decay: 0.5,
+        first_message_deliveries_cap: 20.0,
+        mesh_message_deliveries_weight: 0.0,
+        mesh_failure_penalty_weight: 0.0,
+        //penalty is (invalid messages)^2 * invalid_weight and it decays slowly
+        invalid_message_deliveries_weight: invalid_weight,
+        invalid_message_deliveries_decay: 0.99,
+        ..Default::default()
+    }
+}
+
+//ban connected peers with bad scores and remove expired bans
+pub fn check_scores(swarm: &mut Swarm<CustomBehav>) {
+    let ban_threshold = CONFIG.peer_scoring.ban_threshold;
+    let gossipsub = &swarm.behaviour().gossipsub;
+    let bad_peers: Vec<(PeerId, f64)> = gossipsub
+        .all_peers()
+        .filter_map(|(peer_id, _)| {
+            gossipsub
+                .peer_score(peer_id)
+                .filter(|score| *score < ban_threshold)
+                .map(|score| (*peer_id, score))
+        })
+        .collect();
+    for (peer_id, score) in bad_peers {
+        ban_peer(
+            swarm,
+            peer_id,
+            CONFIG.peer_scoring.ban_minutes,
+            &format!("gossipsub score {:.2}", score),
+        );
+    }
+    lift_expired_bans();
+}
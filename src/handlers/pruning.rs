@@ -0,0 +1,158 @@
+// Pruned storage mode (prune_keep_blocks in relay.json)
+//
+// A pruned relay keeps the whole UTXO set, all of the block headers and the bodies of the last N
+// blocks. Older blocks are moved from Blocks to the Headers collection ({header} documents) and
+// their confirmed reciepts are removed. Pruned relays can't make snapshots or reindex, and they
+// advertise it in their handshake so others don't sync old blocks from them.
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use mongodb::{
+    bson::{doc, from_document, Document},
+    options::{FindOneOptions, FindOptions, ReplaceOptions},
+    Collection,
+};
+use tokio::time::sleep;
+
+use super::{
+    block_sync::local_tip, config::CONFIG, create_log::write_log, db_connection::blockchain_db,
+    structures::BlockHeader,
+};
+
+//max number of blocks that are moved to Headers in one round
+const PRUNE_BATCH: i64 = 1000;
+
+pub fn is_pruned() -> bool {
+    CONFIG.prune_keep_blocks.is_some()
+}
+
+//remove old block bodies and reciepts on schedule
+pub async fn pruner() {
+    let keep = match CONFIG.prune_keep_blocks {
+        Some(keep) => keep.max(1),
+        None => return,
+    };
+    write_log(&format!(
+        "pruned storage mode, bodies of the last {} blocks are kept",
+        keep
+    ));
+    loop {
+        match prune(keep).await {
+            Ok(0) => {}
+            Ok(pruned) => write_log(&format!("{} old blocks pruned", pruned)),
+            Err(e) => write_log(&format!("pruning problem: {}", e)),
+        }
+        sleep(Duration::from_secs(CONFIG.prune_every_minutes * 60)).await;
+    }
+}
+
+//move blocks below tip - keep to Headers and remove their reciepts
+async fn prune(keep: i64) -> Result<u64, String> {
+    let tip = match local_tip().await {
+        Ok(Some(tip)) => tip.number,
+        Ok(None) => return Ok(0),
+        Err(_) => return Err("finding last block problem".to_string()),
+    };
+    let cutoff = tip - keep + 1;
+    let db = blockchain_db().await?;
+    let blocks_coll: Collection<Document> = db.collection("Blocks");
+    let headers_coll: Collection<Document> = db.collection("Headers");
+    let reciept_coll: Collection<Document> = db.collection("reciept");
+
+    let mut pruned = 0;
+    loop {
+        let option = FindOptions::builder()
+            .sort(doc! {"header.number": 1})
+            .limit(PRUNE_BATCH)
+            .projection(doc! {"header": 1})
+            .build();
+        let mut old_blocks = blocks_coll
+            .find(doc! {"header.number": {"$lt": cutoff}}, option)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut batch = 0;
+        while let Some(doc) = old_blocks.next().await {
+            let doc = doc.map_err(|e| e.to_string())?;
+            let header = doc.get_document("header").map_err(|e| e.to_string())?;
+            let number = header.get_i64("number").map_err(|e| e.to_string())?;
+
+            //header is saved before its block is removed, so a crash never loses a header
+            let header_doc = doc! {"header": header.clone()};
+            let option = ReplaceOptions::builder().upsert(true).build();
+            headers_coll
+                .replace_one(doc! {"header.number": number}, header_doc, option)
+                .await
+                .map_err(|e| e.to_string())?;
+            blocks_coll
+                .delete_one(doc! {"header.number": number}, None)
+                .await
+                .map_err(|e| e.to_string())?;
+            batch += 1;
+        }
+        pruned += batch;
+        if batch < PRUNE_BATCH as u64 {
+            break;
+        }
+    }
+
+    reciept_coll
+        .delete_many(doc! {"block_number": {"$lt": cutoff}}, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(pruned)
+}
+
+//number of the oldest block that still has its body
+pub async fn oldest_body() -> Option<i64> {
+    let db = blockchain_db().await.ok()?;
+    let blocks_coll: Collection<Document> = db.collection("Blocks");
+    let option = FindOneOptions::builder()
+        .sort(doc! {"header.number": 1})
+        .projection(doc! {"header.number": 1})
+        .build();
+    match blocks_coll.find_one(None, option).await {
+        Ok(Some(doc)) => doc.get_document("header").ok()?.get_i64("number").ok(),
+        _ => None,
+    }
+}
+
+//check if body of a block is pruned
+pub async fn is_pruned_block(number: i64) -> bool {
+    match blockchain_db().await {
+        Ok(db) => {
+            let headers_coll: Collection<Document> = db.collection("Headers");
+            matches!(
+                headers_coll
+                    .find_one(doc! {"header.number": number}, None)
+                    .await,
+                Ok(Some(_))
+            )
+        }
+        Err(_) => false,
+    }
+}
+
+//headers of pruned blocks in a range
+pub async fn pruned_headers(from: i64, to: i64) -> Vec<BlockHeader> {
+    let mut headers = Vec::new();
+    if let Ok(db) = blockchain_db().await {
+        let headers_coll: Collection<Document> = db.collection("Headers");
+        let filter = doc! {"header.number": {"$gte": from, "$lte": to}};
+        let option = FindOptions::builder()
+            .sort(doc! {"header.number": 1})
+            .build();
+        if let Ok(mut cursor) = headers_coll.find(filter, option).await {
+            while let Some(Ok(doc)) = cursor.next().await {
+                match doc
+                    .get_document("header")
+                    .map(|header| from_document::<BlockHeader>(header.clone()))
+                {
+                    Ok(Ok(header)) => headers.push(header),
+                    _ => break,
+                }
+            }
+        }
+    }
+    headers
+}
@@ -0,0 +1,72 @@
+// Reachability of the relay, served by the /reachability RPC
+//
+// Identify tells the relay the addresses that other peers see (observed addresses) and autonat
+// asks other peers to dial them back. Addresses that are dialed back are confirmed external
+// addresses; the first one is written to myaddress.dat and announced to the network.
+
+use std::sync::Mutex;
+
+use libp2p::{autonat::NatStatus, Multiaddr};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Reachability {
+    //public, private or unknown
+    pub status: String,
+    //address that autonat dialed back when the relay became public
+    pub public_address: Option<String>,
+    pub confirmed_addresses: Vec<String>,
+}
+
+impl Default for Reachability {
+    fn default() -> Self {
+        Self {
+            status: "unknown".to_string(),
+            public_address: None,
+            confirmed_addresses: Vec::new(),
+        }
+    }
+}
+
+pub static REACHABILITY: Lazy<Mutex<Reachability>> =
+    Lazy::new(|| Mutex::new(Reachability::default()));
+
+pub fn set_nat_status(nat_status: &NatStatus) {
+    let mut reachability = REACHABILITY.lock().unwrap();
+    match nat_status {
+        NatStatus::Public(addr) => {
+            reachability.status = "public".to_string();
+            reachability.public_address = Some(addr.to_string());
+        }
+        NatStatus::Private => {
+            reachability.status = "private".to_string();
+            reachability.public_address = None;
+        }
+        NatStatus::Unknown => {
+            reachability.status = "unknown".to_string();
+            reachability.public_address = None;
+        }
+    }
+}
+
+pub fn add_confirmed_address(addr: &Multiaddr) {
+    let mut reachability = REACHABILITY.lock().unwrap();
+    let addr = addr.to_string();
+    if !reachability.confirmed_addresses.contains(&addr) {
+        reachability.confirmed_addresses.push(addr);
+    }
+}
+
+pub fn remove_confirmed_address(addr: &Multiaddr) {
+    let addr = addr.to_string();
+    REACHABILITY
+        .lock()
+        .unwrap()
+        .confirmed_addresses
+        .retain(|confirmed| confirmed != &addr);
+}
+
+pub fn reachability() -> Reachability {
+    REACHABILITY.lock().unwrap().clone()
+}
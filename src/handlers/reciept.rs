@@ -1,12 +1,14 @@
 use std::str::FromStr;
 
 use super::{
+    create_log::write_log,
     db_connection::blockchain_db,
     structures::{BlockHeader, CoinbaseTransaction, Reciept, Transaction},
 };
 
 use mongodb::{
     bson::{doc, to_document, Document},
+    options::ReplaceOptions,
     Collection,
 };
 use rust_decimal::Decimal;
@@ -17,85 +19,82 @@ pub async fn insert_reciept(
     satatus: String,
     description: String,
 ) {
-    match blockchain_db().await {
-        Ok(db) => {
-            let reciept_coll: Collection<Document> = db.collection("reciept");
-            let mut to = String::new();
-            for output in transaction.output.output_data.utxos.clone() {
-                if output.output_unspent.public_key
-                    != transaction.output.output_data.sigenr_public_keys[0].to_string()
-                {
-                    to.push_str(&output.output_unspent.public_key);
-                }
-            }
-            let reciept = Reciept {
-                block_number,
-                hash: transaction.tx_hash.clone(),
-                from: transaction.output.output_data.sigenr_public_keys[0].to_string(),
-                to,
-                value: transaction.value,
-                fee: transaction.fee,
-                status: satatus.clone(),
-                description,
-                date: transaction.date,
-            };
+    //transactions without signer don't have reciepts
+    if transaction.output.output_data.sigenr_public_keys.is_empty() {
+        return;
+    }
+    if let Ok(db) = blockchain_db().await {
+        let reciept_coll: Collection<Document> = db.collection("reciept");
+        let reciept = tx_reciept(&transaction, block_number, satatus, description);
 
-            let reciept_document = to_document(&reciept.clone()).unwrap();
-            let filter = doc! {"hash": reciept.hash.clone()};
-            let find_rec = reciept_coll.find_one(filter.clone(), None).await.unwrap();
-            match find_rec {
-                Some(_) => {
-                    reciept_coll
-                        .replace_one(filter, reciept_document, None)
-                        .await
-                        .unwrap();
-                }
-                None => {
-                    reciept_coll
-                        .insert_one(reciept_document, None)
-                        .await
-                        .unwrap();
-                }
+        let reciept_document = match to_document(&reciept) {
+            Ok(doc) => doc,
+            Err(e) => {
+                write_log(&format!("reciept document problem: {}", e));
+                return;
             }
+        };
+        //replace the reciept of the transaction or insert it
+        let filter = doc! {"hash": reciept.hash.clone()};
+        let option = ReplaceOptions::builder().upsert(true).build();
+        if let Err(e) = reciept_coll
+            .replace_one(filter, reciept_document, option)
+            .await
+        {
+            write_log(&format!("inserting reciept problem: {}", e));
         }
-        Err(_) => {}
     }
 }
 
-pub async fn coinbase_reciept(
-    transaction: CoinbaseTransaction,
+//reciept of a transaction
+pub fn tx_reciept(
+    transaction: &Transaction,
     block_number: Option<i64>,
     satatus: String,
     description: String,
-    block_header: BlockHeader,
-) {
-    match blockchain_db().await {
-        Ok(db) => {
-            let reciept_coll: Collection<Document> = db.collection("reciept");
-            let mut to = String::new();
-            for output in transaction.output.utxos.clone() {
-                to.clear();
-                to.push_str(&output.output_unspent.public_key);
-                let reciept = Reciept {
-                    block_number,
-                    hash: transaction.tx_hash.clone(),
-                    from: "Coinbase".to_string(),
-                    to: to.clone(),
-                    value: output.output_unspent.unspent,
-                    fee: Decimal::from_str("0.0").unwrap(),
-                    status: satatus.clone(),
-                    description: description.clone(),
-                    date: block_header.date.clone(),
-                };
-
-                let reciept_document = to_document(&reciept.clone()).unwrap();
-
-                reciept_coll
-                    .insert_one(reciept_document, None)
-                    .await
-                    .unwrap();
-            }
+) -> Reciept {
+    let mut to = String::new();
+    for output in transaction.output.output_data.utxos.iter() {
+        if output.output_unspent.public_key
+            != transaction.output.output_data.sigenr_public_keys[0].to_string()
+        {
+            to.push_str(&output.output_unspent.public_key);
         }
-        Err(_) => {}
     }
+    Reciept {
+        block_number,
+        hash: transaction.tx_hash.clone(),
+        from: transaction.output.output_data.sigenr_public_keys[0].to_string(),
+        to,
+        value: transaction.value,
+        fee: transaction.fee,
+        status: satatus,
+        description,
+        date: transaction.date.clone(),
+    }
+}
+
+//reciepts of a coinbase, one for each output
+pub fn coinbase_reciepts(
+    transaction: &CoinbaseTransaction,
+    block_number: Option<i64>,
+    satatus: String,
+    description: String,
+    block_header: &BlockHeader,
+) -> Vec<Reciept> {
+    let mut reciepts = Vec::new();
+    for output in transaction.output.utxos.iter() {
+        reciepts.push(Reciept {
+            block_number,
+            hash: transaction.tx_hash.clone(),
+            from: "Coinbase".to_string(),
+            to: output.output_unspent.public_key.clone(),
+            value: output.output_unspent.unspent,
+            fee: Decimal::from_str("0.0").unwrap(),
+            status: satatus.clone(),
+            description: description.clone(),
+            date: block_header.date.clone(),
+        });
+    }
+    reciepts
 }
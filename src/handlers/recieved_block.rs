@@ -1,183 +1,202 @@
-use std::process::Command;
+use std::collections::HashSet;
 
 use libp2p::{identity::PublicKey, PeerId};
+use rayon::prelude::*;
+use rust_decimal::Decimal;
 use sha2::{Digest, Sha256};
 use sp_core::Pair;
 
 use crate::handlers::create_log::write_log;
 
 use super::{
+    block_apply::{apply_block, BLOCK_WRITES},
+    chain_spec::{CHAIN_SPEC, GENESIS_PREVHASH},
     db_connection::blockchain_db,
-    reciept::{coinbase_reciept, insert_reciept},
-    structures::{Block, FullNodes, GossipMessage, UtxoData, UTXO},
+    migrations::mark_for_reindex,
+    structures::{Block, FullNodes, GossipMessage, Transaction},
+    utxo_store::find_utxo,
 };
 
 use mongodb::{
     bson::{doc, from_document, to_document, Document},
     options::FindOneOptions,
-    Collection,
+    Collection, Database,
 };
 
-//interpreter of messages.................................................................................
-pub async fn verifying_block<'a>(
-    str_msg: &String,
-    leader: &mut String,
-    fullnode_subs: &mut Vec<FullNodes>,
+//validate a block with the leader of this round and insert it to database
+//(leader and fullnodes are changed with next_round after it, so it can run out of the swarm loop)
+pub async fn store_block<'a>(
+    gossip_message: &GossipMessage,
+    leader: &str,
+    allow_genesis: bool,
 ) -> Result<(), &'a str> {
-    match serde_json::from_str::<GossipMessage>(&str_msg) {
-        Ok(gossip_message) => {
-            match blockchain_db().await {
-                Ok(db) => {
-                    let block_coll: Collection<Document> = db.collection("Blocks");
-                    let filter =
-                        doc! {"header.blockhash": gossip_message.block.header.blockhash.clone()};
-                    let block = block_coll.find_one(filter, None).await;
-                    match block {
-                        Ok(is) => {
-                            if is.is_none() {
-                                let validator_peerid: PeerId =
-                                    gossip_message.block.header.validator.parse().unwrap();
-                                //check leader that is equal with curren leader in our leader or not
-                                let mut validate_leader = true;
-                                if leader.len() > 0 {
-                                    let current_leader: PeerId = leader.parse().unwrap();
-                                    if current_leader == validator_peerid {
-                                        validate_leader = true
-                                    } else {
-                                        validate_leader = false
-                                    }
-                                }
+    match blockchain_db().await {
+        Ok(db) => {
+            let block_coll: Collection<Document> = db.collection("Blocks");
+            let filter = doc! {"header.blockhash": gossip_message.block.header.blockhash.clone()};
+            let block = block_coll.find_one(filter, None).await;
+            match block {
+                Ok(is) => {
+                    if is.is_none() {
+                        let validator_peerid: PeerId =
+                            match gossip_message.block.header.validator.parse() {
+                                Ok(pid) => pid,
+                                Err(_) => return Err("validator pubkey error"),
+                            };
+                        //check leader that is equal with curren leader in our leader or not
+                        //(leader comes from next_leader of the last block, it may not be a peer id)
+                        let mut validate_leader = true;
+                        if !leader.is_empty() {
+                            validate_leader =
+                                leader.parse::<PeerId>().ok() == Some(validator_peerid);
+                        }
 
-                                if validate_leader {
-                                    //get validator public key
-                                    let validator_publickey = PublicKey::try_decode_protobuf(
-                                        &gossip_message.block.header.block_signature.peer_public,
-                                    );
-                                    match validator_publickey {
-                                        Ok(pubkey) => {
-                                            //check validator peerid
-                                            let check_pid_with_public_key =
-                                                PeerId::from_public_key(&pubkey)
-                                                    == validator_peerid;
-
-                                            //check block signature
-                                            let str_block_body_for_verify =
-                                                gossip_message.block.body.coinbase.tx_hash.clone();
-
-                                            let verify_block_sign = sp_core::ecdsa::Pair::verify(
-                                                &gossip_message
-                                                    .block
-                                                    .header
-                                                    .block_signature
-                                                    .signature[0],
-                                                str_block_body_for_verify,
-                                                &gossip_message
-                                                    .block
-                                                    .header
-                                                    .block_signature
-                                                    .wallet_public,
-                                            );
-
-                                            if check_pid_with_public_key {
-                                                if verify_block_sign {
-                                                    match submit_block(
-                                                        gossip_message,
-                                                        leader,
-                                                        fullnode_subs,
-                                                    )
-                                                    .await
-                                                    {
-                                                        Ok(_) => {
-                                                            match Command::new("mongodump")
-                                                                .arg("--db")
-                                                                .arg("Blockchain")
-                                                                .arg("--out")
-                                                                .arg("/etc/dump")
-                                                                .output()
-                                                            {
-                                                                Ok(_) => {
-                                                                    match Command::new("zip")
-                                                                        .arg("-r")
-                                                                        .arg("/home/blockchain.zip")
-                                                                        .arg("/etc/dump/Blockchain")
-                                                                        .output()
-                                                                    {
-                                                                        Ok(_) => Ok(()),
-                                                                        Err(e) => {
-                                                                            write_log(&format!(
-                                                                                "{:?}",
-                                                                                e
-                                                                            ));
-                                                                            Ok(())
-                                                                        }
-                                                                    }
-                                                                }
-                                                                Err(e) => {
-                                                                    write_log(&format!("{:?}", e));
-                                                                    Ok(())
-                                                                }
-                                                            }
-                                                        }
-                                                        Err(e) => {
-                                                            if e != "reject" {
-                                                                Err("submit block problem")
-                                                            } else {
-                                                                Err("reject")
-                                                            }
-                                                        }
-                                                    }
-                                                } else {
-                                                    write_log(
-                                                                "verify block sign error! recieved block (line 131)",
-                                                            );
-                                                    Err("block sign error")
-                                                }
-                                            } else {
-                                                write_log(
-                                                            "check pid with public key error! recieved block (line 137)"
-                                                            ,
-                                                        );
-                                                Err("check pid error")
-                                            }
-                                        }
-                                        Err(_) => {
-                                            write_log(
-                                                        "validator public key error! recieved block (line 145)",
-                                                    );
-                                            Err("validator pubkey error")
-                                        }
+                        if !CHAIN_SPEC.check_reward(&gossip_message.block) {
+                            write_log("block reward is not equal to chain spec! recieved block");
+                            return Err("reward problem");
+                        }
+
+                        if validate_leader {
+                            verify_block_crypto(&gossip_message.block).await?;
+                            if let Err(e) = check_block_inputs(&gossip_message.block, &db).await {
+                                write_log(&format!(
+                                    "block {} inputs problem: {}! recieved block",
+                                    gossip_message.block.header.number, e
+                                ));
+                                return Err(e);
+                            }
+                            match submit_block(gossip_message, allow_genesis).await {
+                                Ok(_) => Ok(()),
+                                Err(e) => {
+                                    if e != "reject" {
+                                        Err("submit block problem")
+                                    } else {
+                                        Err("reject")
                                     }
-                                } else {
-                                    write_log("validate leader error! recieved block (line 151)");
-                                    Err("leader problem")
                                 }
-                            } else {
-                                return Err("reject");
                             }
+                        } else {
+                            //rounds of relays can differ for a while, it is not a wrong block
+                            write_log("validate leader error! recieved block (line 151)");
+                            Err("reject")
                         }
-                        Err(_) => return Err("reject"),
+                    } else {
+                        return Err("reject");
                     }
                 }
                 Err(_) => return Err("reject"),
             }
         }
-        Err(_) => Err("reject"),
+        Err(_) => return Err("reject"),
     }
 }
 
-//check block in database and check transactions in mempool and then instert it to database
-async fn submit_block<'a>(
-    gossip_message: GossipMessage,
+//set block generator waiting for next round and set the next leader
+pub fn next_round(
+    gossip_message: &GossipMessage,
     leader: &mut String,
     fullnode_subs: &mut Vec<FullNodes>,
+) {
+    //genesis doesn't change waiting of fullnodes
+    if gossip_message.block.header.prevhash != GENESIS_PREVHASH {
+        for i in 0..fullnode_subs.len() {
+            if fullnode_subs[i].peer_id.to_string() == gossip_message.block.header.validator
+                && gossip_message.next_leader != gossip_message.block.header.validator
+            {
+                fullnode_subs[i].waiting = fullnode_subs.len() as i64;
+            } else if fullnode_subs[i].waiting > 0
+                && fullnode_subs[i].peer_id.to_string() != gossip_message.next_leader
+            {
+                fullnode_subs[i].waiting = fullnode_subs[i].waiting - 1;
+            } else if fullnode_subs[i].peer_id.to_string() == gossip_message.next_leader {
+                fullnode_subs[i].waiting = 0;
+            }
+        }
+    }
+
+    //check next leader
+    leader.clear();
+    leader.push_str(&gossip_message.next_leader);
+}
+
+//cpu bound stage of block validation: block signature and signatures and hashes of all of the
+//transactions (transactions are checked in parallel on the rayon thread pool)
+pub fn check_block_crypto<'a>(block: &Block) -> Result<(), &'a str> {
+    check_block_sign(block)?;
+    if check_txs_crypto(&block.body.transactions) {
+        Ok(())
+    } else {
+        write_log("transaction hash or sign problem! recieved block");
+        Err("transaction hash or sign problem")
+    }
+}
+
+//check signatures and hashes of transactions in parallel
+pub fn check_txs_crypto(txs: &[Transaction]) -> bool {
+    txs.par_iter().all(check_tx_hashes)
+}
+
+//run check_block_crypto on the blocking thread pool, so the executor is not blocked by it
+//(stateful stage of validation with UTXOs comes after it)
+pub async fn verify_block_crypto(block: &Block) -> Result<(), &'static str> {
+    let block = block.clone();
+    match tokio::task::spawn_blocking(move || check_block_crypto(&block)).await {
+        Ok(result) => result,
+        Err(_) => Err("verifying block problem"),
+    }
+}
+
+//check validator peer id and block signature of a block
+pub fn check_block_sign<'a>(block: &Block) -> Result<(), &'a str> {
+    let validator_peerid: PeerId = match block.header.validator.parse() {
+        Ok(pid) => pid,
+        Err(_) => return Err("validator pubkey error"),
+    };
+    //get validator public key
+    match PublicKey::try_decode_protobuf(&block.header.block_signature.peer_public) {
+        Ok(pubkey) => {
+            //check validator peerid
+            if PeerId::from_public_key(&pubkey) != validator_peerid {
+                write_log("check pid with public key error! recieved block (line 137)");
+                return Err("check pid error");
+            }
+
+            //check block signature
+            let verify_block_sign = match block.header.block_signature.signature.first() {
+                Some(signature) => sp_core::ecdsa::Pair::verify(
+                    signature,
+                    block.body.coinbase.tx_hash.clone(),
+                    &block.header.block_signature.wallet_public,
+                ),
+                None => false,
+            };
+            if verify_block_sign {
+                Ok(())
+            } else {
+                write_log("verify block sign error! recieved block (line 131)");
+                Err("block sign error")
+            }
+        }
+        Err(_) => {
+            write_log("validator public key error! recieved block (line 145)");
+            Err("validator pubkey error")
+        }
+    }
+}
+
+//check block in database and check transactions in mempool and then instert it to database
+async fn submit_block<'a>(
+    gossip_message: &GossipMessage,
+    allow_genesis: bool,
 ) -> Result<(), &'a str> {
     match blockchain_db().await {
         Ok(db) => {
             let blocks_coll: Collection<Document> = db.collection("Blocks");
-            let utxos_coll: Collection<Document> = db.collection("UTXOs");
-            let reciept_coll: Collection<Document> = db.collection("reciept");
             let filter = doc! {"header.blockhash": gossip_message.block.header.blockhash.clone()};
-            let same_block = blocks_coll.find_one(filter, None).await.unwrap();
+            let same_block = match blocks_coll.find_one(filter, None).await {
+                Ok(same_block) => same_block,
+                Err(_) => return Err("reject"),
+            };
 
             let last_block_filter = doc! {"header.number": -1};
             let last_block_find_opt = FindOneOptions::builder().sort(last_block_filter).build();
@@ -187,190 +206,103 @@ async fn submit_block<'a>(
                 Ok(doc) => {
                     match doc {
                         Some(last_block_document) => {
-                            let last_block: Block = from_document(last_block_document).unwrap();
+                            let last_block: Block = match from_document(last_block_document) {
+                                Ok(last_block) => last_block,
+                                Err(_) => {
+                                    write_log("last block document problem! recieved_block");
+                                    return Err("reject");
+                                }
+                            };
 
-                            let block_verify =
-                                check_txs(gossip_message.clone(), utxos_coll.clone()).await; //remove transaction if it is in mempool or remove from UTXOs collection if it is not in mempool
+                            match same_block {
+                                None => {
+                                    if last_block.header.blockhash
+                                        == gossip_message.block.header.prevhash
+                                    {
+                                        let _writes = BLOCK_WRITES.lock().await;
+                                        let new_block_doc = match to_document(&gossip_message.block)
+                                        {
+                                            Ok(doc) => doc,
+                                            Err(_) => return Err("reject"),
+                                        };
+                                        //insert block to DB (unique indexes reject a second block with the same hash or number)
+                                        if blocks_coll
+                                            .insert_one(new_block_doc, None)
+                                            .await
+                                            .is_err()
+                                        {
+                                            write_log("block inserting problem! recieved_block");
+                                            return Err("reject");
+                                        }
 
-                            if block_verify {
-                                match same_block {
-                                    None => {
-                                        if last_block.header.blockhash
-                                            == gossip_message.block.header.prevhash
+                                        //spend inputs and add outputs and reciepts of the block
+                                        //(our database problem, the block is not wrong)
+                                        if let Err(e) =
+                                            apply_inserted_block(&db, &gossip_message.block).await
                                         {
-                                            let new_block_doc =
-                                                to_document(&gossip_message.block).unwrap();
-                                            blocks_coll
-                                                .insert_one(new_block_doc, None)
-                                                .await
-                                                .unwrap(); //insert block to DB
-
-                                            handle_block_reward(
-                                                gossip_message.clone(),
-                                                utxos_coll.clone(),
-                                            )
-                                            .await; //insert or update node utxos for rewards and fees
-
-                                            handle_tx_utxos(
-                                                gossip_message.clone(),
-                                                utxos_coll.clone(),
-                                            )
-                                            .await;
-
-                                            //set block generator waiting for next round
-                                            for i in 0..fullnode_subs.len() {
-                                                if fullnode_subs[i].peer_id.to_string()
-                                                    == gossip_message.block.header.validator
-                                                    && gossip_message.next_leader
-                                                        != gossip_message.block.header.validator
-                                                {
-                                                    fullnode_subs[i].waiting =
-                                                        fullnode_subs.len() as i64;
-                                                } else if fullnode_subs[i].waiting > 0
-                                                    && fullnode_subs[i].peer_id.to_string()
-                                                        != gossip_message.next_leader
-                                                {
-                                                    fullnode_subs[i].waiting =
-                                                        fullnode_subs[i].waiting - 1;
-                                                } else if fullnode_subs[i].peer_id.to_string()
-                                                    == gossip_message.next_leader
-                                                {
-                                                    fullnode_subs[i].waiting = 0;
-                                                }
-                                            }
-
-                                            //update utxos in database for transactions
-                                            //check next leader
-                                            leader.clear();
-                                            leader.push_str(&gossip_message.next_leader);
-                                            Ok(())
-                                        } else {
-                                            write_log("block prev hash problem! recieved block (line 241)");
-                                            Err("problem")
+                                            write_log(&format!(
+                                                "applying block problem: {}! recieved_block",
+                                                e
+                                            ));
+                                            return Err("reject");
                                         }
-                                    }
-                                    Some(_) => {
-                                        write_log("find same block! recieved block (line 246)");
-                                        Err("problem")
+
+                                        Ok(())
+                                    } else {
+                                        //block of another round or fork, our tip may be behind
+                                        write_log(
+                                            "block prev hash problem! recieved block (line 241)",
+                                        );
+                                        Err("reject")
                                     }
                                 }
-                            } else {
-                                write_log(
-                                    "check trx in block verify problem! recieved block (line 252)",
-                                );
-                                Err("problem")
+                                Some(_) => {
+                                    write_log("find same block! recieved block (line 246)");
+                                    Err("reject")
+                                }
                             }
                         }
                         None => {
-                            if gossip_message.block.header.prevhash
-                                == "This block is Genesis".to_string()
-                                && fullnode_subs.len() < 2
+                            //database is empty, genesis is inserted without removing anything
+                            //(resetting the database is only done with reset-db command)
+                            if gossip_message.block.header.prevhash == GENESIS_PREVHASH
+                                && allow_genesis
                             {
-                                match blocks_coll.delete_many(doc! {}, None).await {
-                                    Ok(_) => match utxos_coll.delete_many(doc! {}, None).await {
-                                        Ok(_) => {
-                                            match reciept_coll.delete_many(doc! {}, None).await {
-                                                Ok(_) => {
-                                                    let new_block_doc =
-                                                        to_document(&gossip_message.block).unwrap();
-                                                    blocks_coll
-                                                        .insert_one(new_block_doc, None)
-                                                        .await
-                                                        .unwrap(); //insert block to DB
-                                                    handle_block_reward(
-                                                        gossip_message.clone(),
-                                                        utxos_coll.clone(),
-                                                    )
-                                                    .await;
-                                                    //update utxos in database for transactions
-                                                    handle_tx_utxos(
-                                                        gossip_message.clone(),
-                                                        utxos_coll.clone(),
-                                                    )
-                                                    .await;
-
-                                                    //check next leader
-                                                    leader.clear();
-                                                    leader.push_str(&gossip_message.next_leader);
-                                                    Ok(())
-                                                }
-                                                Err(_) => {
-                                                    write_log("remove reciept collection problem! recieved_block(line 291)");
-                                                    Err("problem")
-                                                }
-                                            }
-                                        }
-                                        Err(_) => {
-                                            write_log("remove utxos collection problem! recieved_block(line 297)");
-                                            Err("problem")
-                                        }
-                                    },
-                                    Err(_) => {
-                                        write_log("remove bocks collection problem! recieved_block(line 302)");
-                                        Err("problem")
-                                    }
+                                if !CHAIN_SPEC.is_genesis(&gossip_message.block.header.blockhash) {
+                                    write_log("genesis block is not the genesis of chain spec! recieved_block");
+                                    return Err("problem");
+                                }
+                                let _writes = BLOCK_WRITES.lock().await;
+                                let new_block_doc = match to_document(&gossip_message.block) {
+                                    Ok(doc) => doc,
+                                    Err(_) => return Err("reject"),
+                                };
+                                //insert block to DB (unique indexes reject a second block with the same hash or number)
+                                if blocks_coll.insert_one(new_block_doc, None).await.is_err() {
+                                    write_log("block inserting problem! recieved_block");
+                                    return Err("reject");
                                 }
+                                //add outputs and reciepts of genesis
+                                if let Err(e) =
+                                    apply_inserted_block(&db, &gossip_message.block).await
+                                {
+                                    write_log(&format!(
+                                        "applying block problem: {}! recieved_block",
+                                        e
+                                    ));
+                                    return Err("reject");
+                                }
+                                Ok(())
                             } else {
-                                Err("problem")
+                                //blocks can't be stored before genesis
+                                Err("reject")
                             }
                         }
                     }
                 }
                 Err(_) => {
-                    if gossip_message.block.header.prevhash == "This block is Genesis".to_string()
-                        && fullnode_subs.len() < 2
-                    {
-                        match blocks_coll.delete_many(doc! {}, None).await {
-                            Ok(_) => {
-                                match utxos_coll.delete_many(doc! {}, None).await {
-                                    Ok(_) => {
-                                        match reciept_coll.delete_many(doc! {}, None).await {
-                                            Ok(_) => {
-                                                let new_block_doc =
-                                                    to_document(&gossip_message.block).unwrap();
-                                                blocks_coll
-                                                    .insert_one(new_block_doc, None)
-                                                    .await
-                                                    .unwrap(); //insert block to DB
-                                                handle_block_reward(
-                                                    gossip_message.clone(),
-                                                    utxos_coll.clone(),
-                                                )
-                                                .await;
-                                                //update utxos in database for transactions
-                                                handle_tx_utxos(
-                                                    gossip_message.clone(),
-                                                    utxos_coll.clone(),
-                                                )
-                                                .await;
-
-                                                //check next leader
-                                                leader.clear();
-                                                leader.push_str(&gossip_message.next_leader);
-                                                Ok(())
-                                            }
-                                            Err(_) => {
-                                                write_log("remove reciept collection problem! recieved_block(line 346)");
-                                                Err("problem")
-                                            }
-                                        }
-                                    }
-                                    Err(_) => {
-                                        write_log("remove utxos collection problem! recieved_block(line 352)");
-                                        Err("probelm")
-                                    }
-                                }
-                            }
-                            Err(_) => {
-                                write_log(
-                                    "remove bocks collection problem! recieved_block(line 359)",
-                                );
-                                Err("probelm")
-                            }
-                        }
-                    } else {
-                        Err("probelm")
-                    }
+                    write_log("finding last block problem! recieved_block");
+                    Err("reject")
                 }
             }
         }
@@ -381,176 +313,203 @@ async fn submit_block<'a>(
     }
 }
 
-async fn check_txs(gossip_message: GossipMessage, utxos_coll: Collection<Document>) -> bool {
-    let mut block_verify = true;
-    for tx in gossip_message.block.body.transactions.clone() {
-        let signed_message = tx.tx_hash.clone();
-
-        //create hash of tx
-        let mut check_hasher = Sha256::new();
-        check_hasher.update(tx.input.input_hash.clone());
-        check_hasher.update(tx.output.output_hash.clone());
-        let check_hash = format!("{:x}", check_hasher.finalize());
-
-        //create hash of tx inputs
-        let tx_input_str = serde_json::to_string(&tx.input.input_data).unwrap();
-        let input_hash = create_hash(tx_input_str);
-
-        //create hash of outputs
-        let tx_output_str = serde_json::to_string(&tx.output.output_data).unwrap();
-        let output_hash = create_hash(tx_output_str);
-
-        //check tx signature
-        let sign_verify = sp_core::ecdsa::Pair::verify(
-            &tx.input.signatures[0],
-            signed_message,
-            &tx.output.output_data.sigenr_public_keys[0],
-        );
-
-        //check hashs
-        let input_checker = tx.input.input_hash == input_hash;
-        let output_checker = tx.output.output_hash == output_hash;
-        let txhash_checker = tx.tx_hash == check_hash;
-
-        if sign_verify && input_checker && output_checker && txhash_checker {
-            let user_utxo_filter = doc! {"public_key": tx.output.output_data.sigenr_public_keys[0].clone().to_string()};
-            let find_utxo_doc = utxos_coll
-                .find_one(user_utxo_filter.clone(), None)
-                .await
-                .unwrap();
-            if let Some(doc) = find_utxo_doc {
-                let mut user_utxo: UTXO = from_document(doc).unwrap();
-                for utxo in tx.input.input_data.utxos {
-                    let index = user_utxo
-                        .utxos
-                        .iter()
-                        .position(|uu| *uu.output_hash == utxo.output_hash);
-                    match index {
-                        Some(i) => {
-                            user_utxo.utxos.remove(i);
-                            let user_utxo_todoc = to_document(&user_utxo).unwrap();
-                            utxos_coll
-                                .replace_one(user_utxo_filter.clone(), user_utxo_todoc, None)
-                                .await
-                                .unwrap();
+//apply a block that is inserted, if it can't be applied the block stays (some of its inputs may
+//be spent already) and the database is marked for reindex
+async fn apply_inserted_block(db: &Database, block: &Block) -> Result<(), String> {
+    if let Err(e) = apply_block(db, block).await {
+        if let Err(mark_e) = mark_for_reindex(db, block.header.number).await {
+            write_log(&format!(
+                "marking database for reindex after block {} problem: {}",
+                block.header.number, mark_e
+            ));
+        }
+        write_log(&format!(
+            "block {} is not fully applied, run reindex before starting the relay again",
+            block.header.number
+        ));
+        return Err(e);
+    }
+    Ok(())
+}
+
+//insert a block that recieved while syncing with other relays after full validation of it
+pub async fn insert_synced_block(block: Block) -> Result<(), ()> {
+    match blockchain_db().await {
+        Ok(db) => {
+            let blocks_coll: Collection<Document> = db.collection("Blocks");
+            let utxos_coll: Collection<Document> = db.collection("UTXOs");
+            if let Err(e) = verify_block_crypto(&block).await {
+                write_log(&format!(
+                    "synced block {} validation problem: {}",
+                    block.header.number, e
+                ));
+                return Err(());
+            }
+            if let Err(e) = check_block_fully(&block, &utxos_coll).await {
+                write_log(&format!(
+                    "synced block {} validation problem: {}",
+                    block.header.number, e
+                ));
+                return Err(());
+            }
+            let _writes = BLOCK_WRITES.lock().await;
+            match to_document(&block) {
+                Ok(block_doc) => match blocks_coll.insert_one(block_doc, None).await {
+                    Ok(_) => match apply_inserted_block(&db, &block).await {
+                        Ok(_) => Ok(()),
+                        Err(e) => {
+                            write_log(&format!(
+                                "applying synced block {} problem: {}",
+                                block.header.number, e
+                            ));
+                            Err(())
                         }
-                        None => {}
-                    }
-                }
+                    },
+                    Err(_) => Err(()),
+                },
+                Err(_) => Err(()),
             }
-        } else {
-            block_verify = false;
         }
+        Err(_) => Err(()),
     }
-    block_verify
 }
 
-async fn handle_block_reward(gossip_message: GossipMessage, utxos_coll: Collection<Document>) {
-    coinbase_reciept(
-        gossip_message.block.body.coinbase.clone(),
-        Some(gossip_message.block.header.number.clone()),
-        "Confirmed".to_string(),
-        "Coinbase".to_string(),
-        gossip_message.block.header.clone(),
-    )
-    .await;
-    for i in gossip_message.block.body.coinbase.output.utxos.clone() {
-        let cb_utxo_filter = doc! {"public_key": i.output_unspent.public_key.clone()};
-        let utxo_doc = utxos_coll
-            .find_one(cb_utxo_filter.clone(), None)
-            .await
-            .unwrap();
-        let utxo = UtxoData {
-            transaction_hash: gossip_message.block.body.coinbase.tx_hash.clone(),
-            unspent: i.output_unspent.unspent.round_dp(12),
-            output_hash: i.hash.clone(),
-            block_number: gossip_message.block.header.number,
+//validation of a block that its transactions are not in our mempool (syncing)
+//all of the inputs must be unspent in UTXOs before the block and outputs can't be more than inputs
+//(stateful stage, signatures and hashes are checked with check_block_crypto before it)
+pub async fn check_block_fully<'a>(
+    block: &Block,
+    utxos_coll: &Collection<Document>,
+) -> Result<(), &'a str> {
+    //check coinbase
+    let coinbase = &block.body.coinbase;
+    if coinbase.coinbase_data.block_len != block.body.transactions.len() {
+        return Err("coinbase block len problem");
+    }
+    if coinbase.coinbase_data.merkel_root != block.header.merkel_root {
+        return Err("coinbase merkel root problem");
+    }
+    if !CHAIN_SPEC.check_reward(block) {
+        return Err("coinbase reward problem");
+    }
+    let mut coinbase_outputs = Decimal::ZERO;
+    for utxo in coinbase.output.utxos.iter() {
+        if utxo.output_unspent.unspent.is_sign_negative() {
+            return Err("negative coinbase output");
+        }
+        coinbase_outputs += utxo.output_unspent.unspent;
+    }
+    if coinbase_outputs > coinbase.value {
+        return Err("coinbase outputs are more than its value");
+    }
+
+    //check transactions
+    let mut spent_in_block = HashSet::new();
+    for tx in block.body.transactions.iter() {
+        let signer = match tx.output.output_data.sigenr_public_keys.first() {
+            Some(signer) => signer.to_string(),
+            None => return Err("transaction signer problem"),
         };
-        if let Some(doc) = utxo_doc {
-            let mut node_utxo: UTXO = from_document(doc).unwrap();
-            let mut output_hash = Vec::new();
-            for i in node_utxo.utxos.clone() {
-                output_hash.push(i.output_hash);
+        let mut inputs = Decimal::ZERO;
+        for utxo in tx.input.input_data.utxos.iter() {
+            if !spent_in_block.insert(utxo.output_hash.clone()) {
+                return Err("double spend in block");
             }
-            let mut exist_hash = 0;
-            for hash in output_hash {
-                if hash == i.hash.clone() {
-                    exist_hash += 1;
-                    break;
-                }
+            match find_utxo(utxos_coll, &utxo.output_hash).await {
+                Ok(Some(unspent)) if unspent.public_key == signer => inputs += unspent.unspent,
+                Ok(_) => return Err("there is not input UTXOs"),
+                Err(_) => return Err("database problem"),
             }
-            if exist_hash == 0 {
-                node_utxo.utxos.push(utxo);
+        }
+
+        let mut outputs = Decimal::ZERO;
+        for utxo in tx.output.output_data.utxos.iter() {
+            if utxo.output_unspent.unspent.is_sign_negative() {
+                return Err("negative transaction output");
             }
-            let node_utxo_to_doc = to_document(&node_utxo).unwrap();
-            utxos_coll
-                .replace_one(cb_utxo_filter.clone(), node_utxo_to_doc, None)
-                .await
-                .unwrap();
-        } else {
-            let node_pub_key = i.output_unspent.public_key.clone();
-            let utxo = UTXO {
-                public_key: node_pub_key,
-                utxos: vec![utxo],
-            };
-            let utxo_to_doc = to_document(&utxo).unwrap();
-            utxos_coll.insert_one(utxo_to_doc, None).await.unwrap();
+            outputs += utxo.output_unspent.unspent;
+        }
+        if outputs.round_dp(12) > inputs.round_dp(12) {
+            return Err("transaction outputs are more than its inputs");
         }
     }
+    Ok(())
 }
 
-async fn handle_tx_utxos(gossip_message: GossipMessage, utxos_coll: Collection<Document>) {
-    for tx in gossip_message.block.body.transactions.clone() {
-        insert_reciept(
-            tx.clone(),
-            Some(gossip_message.block.header.number),
-            "Confirmed".to_string(),
-            "".to_string(),
-        )
-        .await;
-        for utxo in tx.output.output_data.utxos {
-            let tx_utxo_filter = doc! {"public_key": &utxo.output_unspent.public_key};
-            let utxo_doc = utxos_coll
-                .find_one(tx_utxo_filter.clone(), None)
-                .await
-                .unwrap();
-            let new_utxo = UtxoData {
-                transaction_hash: tx.tx_hash.clone(),
-                unspent: utxo.output_unspent.unspent.round_dp(12),
-                output_hash: utxo.hash.clone(),
-                block_number: gossip_message.block.header.number,
-            };
-            if let Some(doc) = utxo_doc {
-                let mut user_utxo: UTXO = from_document(doc).unwrap();
-                let mut user_utxos_outout_hashs = Vec::new();
-                for i in user_utxo.utxos.clone() {
-                    user_utxos_outout_hashs.push(i.output_hash);
-                }
-                let mut exist_hash = 0;
-                for hash in user_utxos_outout_hashs {
-                    if hash == utxo.hash.clone() {
-                        exist_hash += 1;
-                        break;
+//inputs of transactions of a gossip block must be unspent outputs of their signers
+//(inputs of transactions in our mempool are spent already, their pending reciepts are checked)
+//a missing input may be spent by another transaction in our mempool, the block is only rejected
+async fn check_block_inputs<'a>(block: &Block, db: &Database) -> Result<(), &'a str> {
+    let utxos_coll: Collection<Document> = db.collection("UTXOs");
+    let reciept_coll: Collection<Document> = db.collection("reciept");
+    let mut spent_in_block = HashSet::new();
+    for tx in block.body.transactions.iter() {
+        let signer = match tx.output.output_data.sigenr_public_keys.first() {
+            Some(signer) => signer.to_string(),
+            None => return Err("transaction signer problem"),
+        };
+        let mut in_mempool = None;
+        for utxo in tx.input.input_data.utxos.iter() {
+            if !spent_in_block.insert(utxo.output_hash.clone()) {
+                return Err("double spend in block");
+            }
+            match find_utxo(&utxos_coll, &utxo.output_hash).await {
+                Ok(Some(unspent)) if unspent.public_key == signer => {}
+                Ok(Some(_)) => return Err("input is not owned by signer"),
+                Ok(None) => {
+                    if in_mempool.is_none() {
+                        let filter = doc! {"hash": &tx.tx_hash, "status": "pending"};
+                        in_mempool = match reciept_coll.find_one(filter, None).await {
+                            Ok(pending) => Some(pending.is_some()),
+                            Err(_) => return Err("reject"),
+                        };
+                    }
+                    if in_mempool != Some(true) {
+                        return Err("reject");
                     }
                 }
-                if exist_hash == 0 {
-                    user_utxo.utxos.push(new_utxo);
-                }
-                let user_utxo_doc = to_document(&user_utxo).unwrap();
-                utxos_coll
-                    .replace_one(tx_utxo_filter.clone(), user_utxo_doc, None)
-                    .await
-                    .unwrap();
-            } else {
-                let public_key = utxo.output_unspent.public_key.clone();
-                let mut utxos = Vec::new();
-                utxos.push(new_utxo);
-                let new_utxo = UTXO { public_key, utxos };
-                let user_utxo_doc = to_document(&new_utxo).unwrap();
-                utxos_coll.insert_one(user_utxo_doc, None).await.unwrap();
+                Err(_) => return Err("reject"),
             }
         }
     }
+    Ok(())
+}
+
+//check signature and hashes of a transaction
+pub fn check_tx_hashes(tx: &Transaction) -> bool {
+    let signed_message = tx.tx_hash.clone();
+
+    //create hash of tx
+    let mut check_hasher = Sha256::new();
+    check_hasher.update(tx.input.input_hash.clone());
+    check_hasher.update(tx.output.output_hash.clone());
+    let check_hash = format!("{:x}", check_hasher.finalize());
+
+    //create hash of tx inputs
+    let tx_input_str = serde_json::to_string(&tx.input.input_data).unwrap();
+    let input_hash = create_hash(tx_input_str);
+
+    //create hash of outputs
+    let tx_output_str = serde_json::to_string(&tx.output.output_data).unwrap();
+    let output_hash = create_hash(tx_output_str);
+
+    //check tx signature
+    let sign_verify = match (
+        tx.input.signatures.first(),
+        tx.output.output_data.sigenr_public_keys.first(),
+    ) {
+        (Some(signature), Some(public_key)) => {
+            sp_core::ecdsa::Pair::verify(signature, signed_message, public_key)
+        }
+        _ => false,
+    };
+
+    //check hashs
+    let input_checker = tx.input.input_hash == input_hash;
+    let output_checker = tx.output.output_hash == output_hash;
+    let txhash_checker = tx.tx_hash == check_hash;
+
+    sign_verify && input_checker && output_checker && txhash_checker
 }
 
 //generate 1 hash from a string
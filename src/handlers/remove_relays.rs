@@ -7,9 +7,9 @@ use std::{
 use libp2p::PeerId;
 use reqwest::Client;
 
-use super::create_log::write_log;
+use super::{config::CONFIG, create_log::write_log};
 
-//remove peer from relays.dat file when it disconnected
+//remove peer from relays.dat file when it disconnected (and from the web registry if it is on)
 pub async fn remove_peer(peerid: PeerId) {
     let mut relay_path = "";
     if OS == "linux" {
@@ -25,7 +25,7 @@ pub async fn remove_peer(peerid: PeerId) {
             Ok(line) => {
                 if !line.contains(&peerid.to_string()) {
                     lines.push(line);
-                } else {
+                } else if CONFIG.discovery.web_registry {
                     let client = Client::new();
                     let post_rm_addr = client
                         .post("https://centichain.org/api/rmaddr")
@@ -1,37 +1,41 @@
 use super::{
-    check_trx::handle_transactions,
-    create_log::write_log,
-    outnodes::handle_outnode,
-    recieved_block::verifying_block,
-    structures::{FullNodes, GossipMessage, Req, Res, Transaction},
-    CustomBehav, 
+    block_sync::{blocks_in_range, headers_in_range},
+    pruning::{is_pruned, oldest_body},
+    storage_pipeline::{BlockJob, BlockOrigin, StoragePipeline, TransactionOrigin},
+    structures::{BlocksReq, FullNodes, GossipMessage, HeadersReq, Req, Res, Transaction},
+    CustomBehav,
 };
-use libp2p::{gossipsub::IdentTopic, request_response::ResponseChannel, PeerId, Swarm};
+use libp2p::{request_response::ResponseChannel, Swarm};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Handshake {
-    wallet: String,
-    first_node: String,
+pub struct Handshake {
+    pub wallet: String,
+    pub first_node: String,
+    //pruned relays only have bodies of the blocks from oldest_body
+    //(relays before pruned storage mode don't send them)
+    #[serde(default)]
+    pub pruned: bool,
+    #[serde(default)]
+    pub oldest_body: Option<i64>,
 }
 
-pub async fn handle_requests(
+//reads of the database are answered by the storage pipeline, so the swarm loop doesn't wait
+pub fn handle_requests(
     request: Req,
     swarm: &mut Swarm<CustomBehav>,
     channel: ResponseChannel<Res>,
     wallet: &mut String,
     fullnode_subs: &mut Vec<FullNodes>,
-    leader: &mut String,
-    clients_topic: IdentTopic,
-    relays: &mut Vec<PeerId>,
-    clients: &mut Vec<PeerId>,
-    relay_topic: IdentTopic,
-    local_peer_id: PeerId,
+    leader: &str,
+    pipeline: &mut StoragePipeline,
 ) {
     if request.req == "handshake".to_string() {
         let mut handshake_res = Handshake {
             wallet: wallet.clone(),
             first_node: String::new(),
+            pruned: is_pruned(),
+            oldest_body: None,
         };
 
         if fullnode_subs.len() > 0 {
@@ -40,56 +44,22 @@ pub async fn handle_requests(
             handshake_res.first_node.push_str(&"yes".to_string());
         }
 
-        let str_handshake_res = serde_json::to_string(&handshake_res).unwrap();
-        let response = Res {
-            res: str_handshake_res,
+        let read = async move {
+            if handshake_res.pruned {
+                handshake_res.oldest_body = oldest_body().await;
+            }
+            serde_json::to_string(&handshake_res).unwrap()
         };
-        match swarm
-            .behaviour_mut()
-            .req_res
-            .send_response(channel, response)
-        {
-            Ok(_) => {}
-            Err(e) => write_log(&format!("{:?}", e)),
+        if let Err(channel) = pipeline.push_read(channel, read) {
+            respond_busy(swarm, channel);
         }
     } else if let Ok(_transaction) = serde_json::from_str::<Transaction>(&request.req) {
-        handle_transactions(request.req.clone()).await; //insert transaction to db
-
-        //send true transaction to sse servers
-        let sse_topic = IdentTopic::new("sse");
-        match swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(sse_topic, request.req.clone())
+        //transaction is checked in storage workers, the client is answered after it
+        let origin = TransactionOrigin::Request { channel };
+        if let Err(TransactionOrigin::Request { channel }) =
+            pipeline.push_transaction(request.req.clone(), origin)
         {
-            Ok(_) => {}
-            Err(_) => {}
-        }
-
-        //send true transaction to connected Validators and relays
-        let send_transaction = swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(clients_topic, request.req);
-        match send_transaction {
-            Ok(_) => {
-                let response = Res {
-                    res: "Your transaction sent.".to_string(),
-                };
-                let _ = swarm
-                    .behaviour_mut()
-                    .req_res
-                    .send_response(channel, response);
-            }
-            Err(_) => {
-                let response = Res {
-                    res: "sending error!".to_string(),
-                };
-                let _ = swarm
-                    .behaviour_mut()
-                    .req_res
-                    .send_response(channel, response);
-            }
+            respond_busy(swarm, channel);
         }
     } else if request.req.clone() == "fullnodes".to_string() {
         let str_fullnodes = serde_json::to_string(&fullnode_subs).unwrap();
@@ -98,64 +68,39 @@ pub async fn handle_requests(
             .behaviour_mut()
             .req_res
             .send_response(channel, response);
-    } else if let Ok(gossipms) = serde_json::from_str::<GossipMessage>(&request.req) {
-        let propagation_source: PeerId = gossipms.block.header.validator.parse().unwrap();
-        match verifying_block(&request.req, leader, fullnode_subs).await {
-            Ok(_) => {
-                match swarm
-                    .behaviour_mut()
-                    .gossipsub
-                    .publish(relay_topic.clone(), request.req.as_bytes())
-                {
-                    Ok(_) => {
-                        let response = Res { res: String::new() };
-                        let _ = swarm
-                            .behaviour_mut()
-                            .req_res
-                            .send_response(channel, response);
-
-                        //send true block to sse servers
-                        let sse_topic = IdentTopic::new("sse");
-                        match swarm
-                            .behaviour_mut()
-                            .gossipsub
-                            .publish(sse_topic, request.req.clone().as_bytes())
-                        {
-                            Ok(_) => {}
-                            Err(_) => {}
-                        }
-                    }
-                    Err(_) => {
-                        // write_log("sending gossip message to relay topic has problem");
-                    }
-                }
-
-                //send true block to connected Validators
-                let validators_topic = IdentTopic::new(local_peer_id.to_string());
-                match swarm
-                    .behaviour_mut()
-                    .gossipsub
-                    .publish(validators_topic, request.req.clone().as_bytes())
-                {
-                    Ok(_) => {}
-                    Err(_) => {}
-                }
-            }
-            Err(e) => {
-                if e != "reject" {
-                    handle_outnode(
-                        propagation_source,
-                        swarm,
-                        clients_topic,
-                        relays,
-                        clients,
-                        relay_topic,
-                        fullnode_subs,
-                    )
-                    .await;
-                    swarm.disconnect_peer_id(propagation_source).unwrap();
-                }
-            }
+    } else if let Ok(headers_req) = serde_json::from_str::<HeadersReq>(&request.req) {
+        let read = async move {
+            let headers = headers_in_range(headers_req).await;
+            serde_json::to_string(&headers).unwrap()
+        };
+        if let Err(channel) = pipeline.push_read(channel, read) {
+            respond_busy(swarm, channel);
         }
+    } else if let Ok(blocks_req) = serde_json::from_str::<BlocksReq>(&request.req) {
+        let read = async move {
+            let blocks = blocks_in_range(blocks_req).await;
+            serde_json::to_string(&blocks).unwrap()
+        };
+        if let Err(channel) = pipeline.push_read(channel, read) {
+            respond_busy(swarm, channel);
+        }
+    } else if let Ok(gossip_message) = serde_json::from_str::<GossipMessage>(&request.req) {
+        //block is validated and stored in the block worker, the validator is answered after it
+        let job = BlockJob {
+            str_msg: request.req,
+            gossip_message,
+            origin: BlockOrigin::Request { channel },
+        };
+        pipeline.push_block(job, leader, fullnode_subs);
     }
 }
+
+fn respond_busy(swarm: &mut Swarm<CustomBehav>, channel: ResponseChannel<Res>) {
+    let response = Res {
+        res: "relay is busy! try again.".to_string(),
+    };
+    let _ = swarm
+        .behaviour_mut()
+        .req_res
+        .send_response(channel, response);
+}
@@ -5,10 +5,10 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use libp2p::{gossipsub::IdentTopic, PeerId, Swarm};
+use libp2p::{PeerId, Swarm};
 
 use super::{
-    create_log::write_log, listening_dialing::start, structures::FullNodes,
+    chain_spec::CHAIN_SPEC, create_log::write_log, listening_dialing::start, structures::FullNodes,
     swarm_config::CustomBehav,
 };
 
@@ -47,8 +47,8 @@ pub async fn run(swarm: Arc<Mutex<Swarm<CustomBehav>>>, local_peer_id: PeerId) {
     let mut my_addresses = Vec::new();
     let mut sync = false;
     let mut syncing_blocks = Vec::new();
-    let relay_topic = IdentTopic::new("relay");
-    let clients_topic = IdentTopic::new("client");
+    let relay_topic = CHAIN_SPEC.topic("relay");
+    let clients_topic = CHAIN_SPEC.topic("client");
 
     start(
         local_peer_id,
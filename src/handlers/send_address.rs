@@ -1,6 +1,6 @@
 use libp2p::{gossipsub::TopicHash, PeerId, Swarm};
 
-use super::{create_log::write_log, CustomBehav};
+use super::{chain_spec::CHAIN_SPEC, create_log::write_log, CustomBehav};
 
 //send listener addresses to another relays and clients
 pub fn send_address(
@@ -12,7 +12,7 @@ pub fn send_address(
     clients: &mut Vec<PeerId>,
     client_topic_subscriber: &mut Vec<PeerId>,
 ) {
-    if topic.to_string() == "relay".to_string() {
+    if topic == CHAIN_SPEC.topic("relay").hash() {
         relay_topic_subscribers.push(peer_id);
         if connections.contains(&peer_id) && clients.len() > 0 {
             match swarm
@@ -26,11 +26,11 @@ pub fn send_address(
         }
     }
 
-    if topic.to_string() == "client".to_string() && connections.contains(&peer_id) {
+    if topic == CHAIN_SPEC.topic("client").hash() && connections.contains(&peer_id) {
         client_topic_subscriber.push(peer_id);
     }
 
-    if topic.to_string() == "sse".to_string() {
+    if topic == CHAIN_SPEC.topic("sse").hash() {
         swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
     }
 }
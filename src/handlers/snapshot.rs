@@ -0,0 +1,586 @@
+// Snapshot archive format (version 2)
+//
+// A snapshot is a zip archive (blockchain.zip) with these entries:
+//   Blocks.bson   - Block documents with header.number <= height, ordered by header.number
+//   UTXOs.bson    - UtxoRecord documents (one per unspent output) at height, made by replaying
+//                   the blocks of Blocks.bson
+//   reciept.bson  - Reciept documents that are confirmed in a block with number <= height
+//   manifest.json - SnapshotManifest: format version, height, tip hash, creation date and
+//                   name, size (bytes) and sha256 (hex) of every .bson entry
+//
+// Every .bson entry is a plain concatenation of BSON documents (the same layout as mongodump).
+// Readers must check the size and sha256 of each entry with manifest.json before using it.
+//
+// Snapshots are made by the background scheduler (snapshot_scheduler.rs) while blocks are stored,
+// so the tip is read between block writes (BLOCK_WRITES) and nothing above it is exported.
+// Next to the archive the relay keeps blockchain.json (SnapshotInfo: the manifest plus size and
+// sha256 of the archive itself). The RPC server signs it with the relay identity key and serves
+// it as SignedManifest on /snapshot/manifest, so downloaders can check who made the archive and
+// which height it covers before they trust it.
+
+use std::{
+    collections::BTreeMap,
+    env::consts::OS,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use chrono::{SubsecRound, Utc};
+use libp2p::{
+    futures::StreamExt,
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use mongodb::{
+    bson::{doc, from_document, to_document, Document},
+    options::{FindOneOptions, FindOptions},
+    Collection,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use super::{
+    block_apply::{BlockChanges, BLOCK_WRITES},
+    chain_spec::GENESIS_PREVHASH,
+    db_connection::blockchain_db,
+    structures::{Block, UtxoRecord},
+};
+
+pub const SNAPSHOT_FORMAT: u32 = 2;
+pub const SNAPSHOT_FILES: [&str; 3] = ["Blocks.bson", "UTXOs.bson", "reciept.bson"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SnapshotFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SnapshotManifest {
+    pub format: u32,
+    pub height: i64,
+    pub tip_hash: String,
+    pub date: String,
+    pub files: Vec<SnapshotFile>,
+}
+
+//manifest of a snapshot with the checksum of its archive
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SnapshotInfo {
+    pub manifest: SnapshotManifest,
+    pub archive: SnapshotFile,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SignedManifest {
+    pub info: SnapshotInfo,
+    //protobuf encoding of the relay identity public key
+    pub public_key: Vec<u8>,
+    //signature of the relay identity key over the json of info
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestRes {
+    pub signed: Option<SignedManifest>,
+    pub status: String,
+}
+
+//documents of the .bson entries that go to the archive writer
+enum ArchiveEntry {
+    Document(Document),
+    EndOfFile,
+}
+
+//documents that wait for the archive writer
+const ARCHIVE_QUEUE: usize = 1024;
+
+//writer that counts and hashes everything that is written to it
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    fn finish(self, name: &str) -> SnapshotFile {
+        SnapshotFile {
+            name: name.to_string(),
+            size: self.size,
+            sha256: format!("{:x}", self.hasher.finalize()),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+//path of the snapshot that is served to other relays
+pub fn snapshot_path() -> &'static str {
+    if OS == "windows" {
+        "blockchain.zip"
+    } else {
+        "/home/blockchain.zip"
+    }
+}
+
+//path of the manifest and checksum of the served snapshot
+pub fn manifest_path() -> &'static str {
+    if OS == "windows" {
+        "blockchain.json"
+    } else {
+        "/home/blockchain.json"
+    }
+}
+
+//path of a snapshot that is downloaded from another relay (never served to others)
+pub fn sync_archive_path() -> &'static str {
+    if OS == "windows" {
+        "sync-blockchain.zip"
+    } else {
+        "/home/sync-blockchain.zip"
+    }
+}
+
+//directory of the last snapshots that are kept by the scheduler
+pub fn snapshots_dir() -> &'static str {
+    if OS == "windows" {
+        "snapshots"
+    } else {
+        "/home/snapshots"
+    }
+}
+
+//write a snapshot of the blockchain at our last block to the path
+pub async fn create_snapshot(path: &str) -> Result<SnapshotManifest, String> {
+    let db = blockchain_db().await?;
+    let blocks_coll: Collection<Document> = db.collection("Blocks");
+    let reciept_coll: Collection<Document> = db.collection("reciept");
+
+    let last_block_opt = FindOneOptions::builder()
+        .sort(doc! {"header.number": -1})
+        .build();
+    //blocks below the tip don't change, the tip is read after the block in work is applied
+    let tip: Block = {
+        let _writes = BLOCK_WRITES.lock().await;
+        match blocks_coll.find_one(None, last_block_opt).await {
+            Ok(Some(doc)) => from_document(doc).map_err(|e| e.to_string())?,
+            Ok(None) => return Err("there is not any block for snapshot".to_string()),
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+    let height = tip.header.number;
+
+    //zip and file writes run on the blocking thread pool, documents are sent to them
+    let (entries, receiver) = mpsc::channel::<ArchiveEntry>(ARCHIVE_QUEUE);
+    let archive_path = path.to_string();
+    let writer = tokio::task::spawn_blocking(move || write_entries(&archive_path, receiver));
+    let read = read_entries(&blocks_coll, &reciept_coll, &tip, &entries).await;
+    drop(entries);
+    let (archive, files) = writer.await.map_err(|e| e.to_string())??;
+    read?;
+
+    let manifest = SnapshotManifest {
+        format: SNAPSHOT_FORMAT,
+        height,
+        tip_hash: tip.header.blockhash,
+        date: Utc::now().round_subsecs(0).to_string(),
+        files,
+    };
+    let str_manifest = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || finish_archive(archive, &str_manifest))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    Ok(manifest)
+}
+
+//send documents of the .bson entries in order, every entry ends with EndOfFile
+async fn read_entries(
+    blocks_coll: &Collection<Document>,
+    reciept_coll: &Collection<Document>,
+    tip: &Block,
+    entries: &mpsc::Sender<ArchiveEntry>,
+) -> Result<(), String> {
+    let height = tip.header.number;
+    let send = |entry: ArchiveEntry| async move {
+        entries
+            .send(entry)
+            .await
+            .map_err(|_| "archive writer stopped".to_string())
+    };
+
+    //blocks
+    let blocks_opt = FindOptions::builder()
+        .sort(doc! {"header.number": 1})
+        .build();
+    let mut blocks = blocks_coll
+        .find(doc! {"header.number": {"$lte": height}}, blocks_opt)
+        .await
+        .map_err(|e| e.to_string())?;
+    //UTXOs collection is changed by new blocks and transactions of the mempool while the snapshot
+    //is made, so UTXOs at the height are made by replaying the blocks (like reindex)
+    let mut utxos: BTreeMap<String, UtxoRecord> = BTreeMap::new();
+    let mut prev_hash = GENESIS_PREVHASH.to_string();
+    while let Some(doc) = blocks.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        let block: Block = from_document(doc.clone()).map_err(|e| e.to_string())?;
+        send(ArchiveEntry::Document(doc)).await?;
+        if block.header.prevhash != prev_hash {
+            return Err(format!(
+                "block {} is not linked to previous block",
+                block.header.number
+            ));
+        }
+        prev_hash = block.header.blockhash.clone();
+        replay_block(&mut utxos, &block)?;
+    }
+    if prev_hash != tip.header.blockhash {
+        return Err("blocks are changed while making snapshot".to_string());
+    }
+    send(ArchiveEntry::EndOfFile).await?;
+
+    //utxos
+    for record in utxos.values() {
+        let doc = to_document(record).map_err(|e| e.to_string())?;
+        send(ArchiveEntry::Document(doc)).await?;
+    }
+    send(ArchiveEntry::EndOfFile).await?;
+
+    //reciepts
+    let mut reciepts = reciept_coll
+        .find(doc! {"block_number": {"$lte": height}}, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(doc) = reciepts.next().await {
+        let doc = doc.map_err(|e| e.to_string())?;
+        send(ArchiveEntry::Document(doc)).await?;
+    }
+    send(ArchiveEntry::EndOfFile).await
+}
+
+fn entry_options() -> FileOptions {
+    FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(true)
+}
+
+//write the .bson entries of SNAPSHOT_FILES to a new archive (blocking)
+//an entry ends when EndOfFile comes or the sender is dropped
+fn write_entries(
+    path: &str,
+    mut entries: mpsc::Receiver<ArchiveEntry>,
+) -> Result<(ZipWriter<File>, Vec<SnapshotFile>), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut archive = ZipWriter::new(file);
+    let mut files = Vec::new();
+    for name in SNAPSHOT_FILES {
+        archive
+            .start_file(name, entry_options())
+            .map_err(|e| e.to_string())?;
+        let mut writer = HashingWriter::new(&mut archive);
+        while let Some(ArchiveEntry::Document(doc)) = entries.blocking_recv() {
+            doc.to_writer(&mut writer).map_err(|e| e.to_string())?;
+        }
+        files.push(writer.finish(name));
+    }
+    Ok((archive, files))
+}
+
+//add manifest.json and finish the archive (blocking)
+fn finish_archive(mut archive: ZipWriter<File>, str_manifest: &str) -> Result<(), String> {
+    archive
+        .start_file("manifest.json", entry_options())
+        .map_err(|e| e.to_string())?;
+    archive
+        .write_all(str_manifest.as_bytes())
+        .map_err(|e| e.to_string())?;
+    archive.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+//spend inputs and add outputs of a block to UTXOs
+fn replay_block(utxos: &mut BTreeMap<String, UtxoRecord>, block: &Block) -> Result<(), String> {
+    let changes = BlockChanges::from_block(block)?;
+    for (output_hash, public_key) in changes.spent {
+        if utxos
+            .get(&output_hash)
+            .is_some_and(|record| record.public_key == public_key)
+        {
+            utxos.remove(&output_hash);
+        }
+    }
+    for record in changes.utxos {
+        utxos.insert(record.output_hash.clone(), record);
+    }
+    Ok(())
+}
+
+//manifest of a snapshot with size and checksum of its archive
+pub fn snapshot_info(
+    manifest: SnapshotManifest,
+    archive_path: &str,
+) -> Result<SnapshotInfo, String> {
+    Ok(SnapshotInfo {
+        manifest,
+        archive: file_checksum(archive_path, "blockchain.zip")?,
+    })
+}
+
+//size and sha256 of a file
+pub fn file_checksum(path: &str, name: &str) -> Result<SnapshotFile, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut writer = HashingWriter::new(io::sink());
+    io::copy(&mut file, &mut writer).map_err(|e| e.to_string())?;
+    Ok(writer.finish(name))
+}
+
+pub fn sign_manifest(info: SnapshotInfo, keypair: &Keypair) -> Result<SignedManifest, String> {
+    let str_info = serde_json::to_string(&info).map_err(|e| e.to_string())?;
+    let signature = keypair
+        .sign(str_info.as_bytes())
+        .map_err(|e| e.to_string())?;
+    Ok(SignedManifest {
+        info,
+        public_key: keypair.public().encode_protobuf(),
+        signature,
+    })
+}
+
+//check signature of a manifest and that it is signed by the relay that we want to sync with
+pub fn verify_manifest(signed: &SignedManifest, relay: &PeerId) -> Result<(), String> {
+    let public_key =
+        PublicKey::try_decode_protobuf(&signed.public_key).map_err(|e| e.to_string())?;
+    if PeerId::from_public_key(&public_key) != *relay {
+        return Err("manifest is not signed by the relay".to_string());
+    }
+    let str_info = serde_json::to_string(&signed.info).map_err(|e| e.to_string())?;
+    if !public_key.verify(str_info.as_bytes(), &signed.signature) {
+        return Err("manifest signature problem".to_string());
+    }
+    if signed.info.manifest.format != SNAPSHOT_FORMAT {
+        return Err(format!(
+            "unknown snapshot format: {}",
+            signed.info.manifest.format
+        ));
+    }
+    Ok(())
+}
+
+//extract .bson files of a snapshot to out_dir after checking them with its manifest
+pub fn extract_snapshot(archive_path: &str, out_dir: &str) -> Result<SnapshotManifest, String> {
+    let file = File::open(archive_path).map_err(|e| e.to_string())?;
+    let mut archive = ZipArchive::new(file).map_err(|e| e.to_string())?;
+
+    let manifest: SnapshotManifest = {
+        let mut entry = archive
+            .by_name("manifest.json")
+            .map_err(|e| e.to_string())?;
+        let mut str_manifest = String::new();
+        entry
+            .read_to_string(&mut str_manifest)
+            .map_err(|e| e.to_string())?;
+        serde_json::from_str(&str_manifest).map_err(|e| e.to_string())?
+    };
+    if manifest.format != SNAPSHOT_FORMAT {
+        return Err(format!("unknown snapshot format: {}", manifest.format));
+    }
+
+    fs::create_dir_all(out_dir).map_err(|e| e.to_string())?;
+    for name in SNAPSHOT_FILES {
+        let expected = match manifest.files.iter().find(|f| f.name == name) {
+            Some(expected) => expected,
+            None => return Err(format!("{} is not in snapshot manifest", name)),
+        };
+        let mut entry = archive.by_name(name).map_err(|e| e.to_string())?;
+        let output = File::create(Path::new(out_dir).join(name)).map_err(|e| e.to_string())?;
+        let mut writer = HashingWriter::new(output);
+        io::copy(&mut entry, &mut writer).map_err(|e| e.to_string())?;
+        let extracted = writer.finish(name);
+        if extracted != *expected {
+            return Err(format!("checksum of {} is not equal to manifest", name));
+        }
+    }
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(files: Vec<SnapshotFile>) -> SnapshotManifest {
+        SnapshotManifest {
+            format: SNAPSHOT_FORMAT,
+            height: 12,
+            tip_hash: "tip".to_string(),
+            date: "2024-01-01 00:00:00 UTC".to_string(),
+            files,
+        }
+    }
+
+    fn info() -> SnapshotInfo {
+        SnapshotInfo {
+            manifest: manifest(Vec::new()),
+            archive: SnapshotFile {
+                name: "blockchain.zip".to_string(),
+                size: 3,
+                sha256: "abc".to_string(),
+            },
+        }
+    }
+
+    fn checksum(name: &str, data: &[u8]) -> SnapshotFile {
+        let mut writer = HashingWriter::new(io::sink());
+        writer.write_all(data).unwrap();
+        writer.finish(name)
+    }
+
+    //directory of a test in the temp directory, it is empty at the start
+    fn test_dir(name: &str) -> String {
+        let dir =
+            std::env::temp_dir().join(format!("relay-snapshot-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    fn write_archive(path: &str, manifest: &SnapshotManifest, entries: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, data) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.start_file("manifest.json", options).unwrap();
+        zip.write_all(serde_json::to_string(manifest).unwrap().as_bytes())
+            .unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn verifies_signed_manifests() {
+        let keypair = Keypair::generate_ed25519();
+        let relay = keypair.public().to_peer_id();
+        let signed = sign_manifest(info(), &keypair).unwrap();
+        assert_eq!(verify_manifest(&signed, &relay), Ok(()));
+
+        //signed by another relay
+        assert!(verify_manifest(&signed, &PeerId::random()).is_err());
+
+        let mut changed = signed.clone();
+        changed.info.manifest.height += 1;
+        assert!(verify_manifest(&changed, &relay).is_err());
+
+        let mut unknown = info();
+        unknown.manifest.format = SNAPSHOT_FORMAT + 1;
+        let unknown = sign_manifest(unknown, &keypair).unwrap();
+        assert!(verify_manifest(&unknown, &relay).is_err());
+    }
+
+    #[test]
+    fn extracts_checked_files() {
+        let dir = test_dir("extract");
+        let entries: [(&str, &[u8]); 3] = [
+            ("Blocks.bson", b"blocks"),
+            ("UTXOs.bson", b"utxos"),
+            ("reciept.bson", b""),
+        ];
+        let files = entries
+            .iter()
+            .map(|(name, data)| checksum(name, data))
+            .collect();
+        let manifest = manifest(files);
+        let archive = format!("{}/blockchain.zip", dir);
+        write_archive(&archive, &manifest, &entries);
+
+        let out_dir = format!("{}/out", dir);
+        assert_eq!(extract_snapshot(&archive, &out_dir), Ok(manifest));
+        for (name, data) in entries {
+            assert_eq!(fs::read(Path::new(&out_dir).join(name)).unwrap(), data);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn writes_entries_of_the_archive_writer() {
+        let dir = test_dir("write");
+        let (entries, receiver) = mpsc::channel::<ArchiveEntry>(ARCHIVE_QUEUE);
+        let block = doc! {"header": {"number": 0_i64}};
+        entries
+            .try_send(ArchiveEntry::Document(block.clone()))
+            .unwrap();
+        entries.try_send(ArchiveEntry::EndOfFile).unwrap();
+        entries.try_send(ArchiveEntry::EndOfFile).unwrap();
+        //reciepts entry ends with the sender
+        drop(entries);
+
+        let archive = format!("{}/blockchain.zip", dir);
+        let (zip, files) = write_entries(&archive, receiver).unwrap();
+        let mut bson = Vec::new();
+        block.to_writer(&mut bson).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                checksum("Blocks.bson", &bson),
+                checksum("UTXOs.bson", b""),
+                checksum("reciept.bson", b"")
+            ]
+        );
+
+        let manifest = manifest(files);
+        finish_archive(zip, &serde_json::to_string(&manifest).unwrap()).unwrap();
+        let out_dir = format!("{}/out", dir);
+        assert_eq!(extract_snapshot(&archive, &out_dir), Ok(manifest));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_in_manifest() {
+        let dir = test_dir("reject");
+        let entries: [(&str, &[u8]); 3] = [
+            ("Blocks.bson", b"blocks"),
+            ("UTXOs.bson", b"utxos"),
+            ("reciept.bson", b"reciepts"),
+        ];
+        let archive = format!("{}/blockchain.zip", dir);
+        let out_dir = format!("{}/out", dir);
+
+        //checksum of another content
+        let mut files: Vec<SnapshotFile> = entries
+            .iter()
+            .map(|(name, data)| checksum(name, data))
+            .collect();
+        files[1] = checksum("UTXOs.bson", b"other utxos");
+        write_archive(&archive, &manifest(files.clone()), &entries);
+        assert!(extract_snapshot(&archive, &out_dir).is_err());
+
+        //file without checksum
+        files.truncate(2);
+        write_archive(&archive, &manifest(files), &entries);
+        assert!(extract_snapshot(&archive, &out_dir).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use super::{
+    block_sync::local_tip,
+    config::CONFIG,
+    create_log::write_log,
+    pruning::is_pruned,
+    snapshot::{
+        create_snapshot, manifest_path, snapshot_info, snapshot_path, snapshots_dir, SnapshotInfo,
+    },
+};
+
+//time between two checks of the chain tip
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SnapshotStatus {
+    pub running: bool,
+    //height and date of the snapshot that is served now
+    pub last_height: Option<i64>,
+    pub last_date: Option<String>,
+    pub last_error: Option<String>,
+    //heights of the snapshots in the snapshots directory
+    pub kept: Vec<i64>,
+    pub every_blocks: i64,
+    pub every_minutes: u64,
+    pub keep: usize,
+}
+
+pub static SNAPSHOT_STATUS: Lazy<Mutex<SnapshotStatus>> = Lazy::new(|| {
+    Mutex::new(SnapshotStatus {
+        every_blocks: CONFIG.snapshot_every_blocks,
+        every_minutes: CONFIG.snapshot_every_minutes,
+        keep: CONFIG.snapshots_to_keep,
+        ..Default::default()
+    })
+});
+
+//make a snapshot every N blocks or every T minutes (if there is a new block) in background
+pub async fn snapshot_scheduler() {
+    //snapshots need all of the blocks from genesis
+    if is_pruned() {
+        write_log("snapshots are not made in pruned storage mode");
+        return;
+    }
+    //continue from the snapshot that is already served
+    if let Ok(str_info) = fs::read_to_string(manifest_path()) {
+        if let Ok(info) = serde_json::from_str::<SnapshotInfo>(&str_info) {
+            let mut status = SNAPSHOT_STATUS.lock().unwrap();
+            status.last_height = Some(info.manifest.height);
+            status.last_date = Some(info.manifest.date);
+        }
+    }
+    SNAPSHOT_STATUS.lock().unwrap().kept = kept_snapshots();
+
+    let every_minutes = Duration::from_secs(CONFIG.snapshot_every_minutes * 60);
+    let mut last_time = Instant::now();
+    loop {
+        sleep(CHECK_INTERVAL).await;
+        let tip = match local_tip().await {
+            Ok(Some(tip)) => tip.number,
+            _ => continue,
+        };
+        let last_height = SNAPSHOT_STATUS.lock().unwrap().last_height;
+        let due = match last_height {
+            Some(height) => {
+                tip >= height + CONFIG.snapshot_every_blocks
+                    || (tip > height && last_time.elapsed() >= every_minutes)
+            }
+            None => true,
+        };
+        if !due {
+            continue;
+        }
+
+        SNAPSHOT_STATUS.lock().unwrap().running = true;
+        let result = make_snapshot().await;
+        let mut status = SNAPSHOT_STATUS.lock().unwrap();
+        status.running = false;
+        match result {
+            Ok(info) => {
+                write_log(&format!(
+                    "snapshot of block {} created",
+                    info.manifest.height
+                ));
+                status.last_height = Some(info.manifest.height);
+                status.last_date = Some(info.manifest.date);
+                status.last_error = None;
+            }
+            Err(e) => {
+                write_log(&format!("snapshot creating problem: {}", e));
+                status.last_error = Some(e);
+            }
+        }
+        status.kept = kept_snapshots();
+        last_time = Instant::now();
+    }
+}
+
+//create a snapshot in a temp file, keep a copy of it and swap it with the served snapshot
+async fn make_snapshot() -> Result<SnapshotInfo, String> {
+    let tmp_archive = format!("{}.tmp", snapshot_path());
+    let manifest = match create_snapshot(&tmp_archive).await {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            fs::remove_file(&tmp_archive).ok();
+            return Err(e);
+        }
+    };
+    let info = snapshot_info(manifest, &tmp_archive)?;
+    let str_info = serde_json::to_string_pretty(&info).map_err(|e| e.to_string())?;
+
+    //keep this snapshot in the snapshots directory
+    fs::create_dir_all(snapshots_dir()).map_err(|e| e.to_string())?;
+    let kept_archive =
+        Path::new(snapshots_dir()).join(format!("blockchain-{}.zip", info.manifest.height));
+    if fs::hard_link(&tmp_archive, &kept_archive).is_err() {
+        fs::copy(&tmp_archive, &kept_archive).map_err(|e| e.to_string())?;
+    }
+    let kept_info = kept_archive.with_extension("json");
+    fs::write(kept_info, &str_info).map_err(|e| e.to_string())?;
+
+    //rename is atomic, downloads that are running keep reading the old file
+    fs::rename(&tmp_archive, snapshot_path()).map_err(|e| e.to_string())?;
+    let tmp_info = format!("{}.tmp", manifest_path());
+    fs::write(&tmp_info, &str_info).map_err(|e| e.to_string())?;
+    fs::rename(&tmp_info, manifest_path()).map_err(|e| e.to_string())?;
+
+    remove_old_snapshots();
+    Ok(info)
+}
+
+//heights of the snapshots in the snapshots directory from newest to oldest
+fn kept_snapshots() -> Vec<i64> {
+    let mut heights = Vec::new();
+    if let Ok(entries) = fs::read_dir(snapshots_dir()) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let height = name
+                .strip_prefix("blockchain-")
+                .and_then(|n| n.strip_suffix(".zip"))
+                .and_then(|n| n.parse::<i64>().ok());
+            if let Some(height) = height {
+                heights.push(height);
+            }
+        }
+    }
+    heights.sort_unstable_by(|a, b| b.cmp(a));
+    heights
+}
+
+fn remove_old_snapshots() {
+    for height in kept_snapshots().into_iter().skip(CONFIG.snapshots_to_keep) {
+        let archive = Path::new(snapshots_dir()).join(format!("blockchain-{}.zip", height));
+        fs::remove_file(archive.with_extension("json")).ok();
+        match fs::remove_file(&archive) {
+            Ok(_) => write_log(&format!("old snapshot of block {} removed", height)),
+            Err(e) => write_log(&format!("removing old snapshot {} problem: {}", height, e)),
+        }
+    }
+}
+
+//current status of snapshots for rpc server
+pub fn snapshot_status() -> SnapshotStatus {
+    SNAPSHOT_STATUS.lock().unwrap().clone()
+}
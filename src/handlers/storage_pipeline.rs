@@ -0,0 +1,368 @@
+// Storage work of gossip messages and requests runs out of the swarm loop
+//
+// The swarm loop only decodes messages and puts their work in bounded queues:
+//   transactions - checked and saved by `transaction_workers` tasks (while syncing only their
+//                  pending reciepts are saved)
+//   blocks       - validated and stored by one task, one block at a time and in order of arrival,
+//                  so every block is checked with the leader that the block before it has set
+// When a queue is full new work is not accepted (gossip work is ignored and requesters get a busy
+// response or no response), so a flood of messages can't stop the swarm or fill the memory. Results
+// go back to the swarm loop that reports gossip messages to gossipsub (only accepted messages are
+// forwarded), publishes good blocks and transactions of clients and penalizes the peers of bad
+// blocks.
+//
+// Reads of requests (headers, blocks and handshake of pruned relays) run in their own tasks, at most
+// `storage_queue_size` at a time, and their responses come back as results too.
+//
+// Workers return errors for bad input and never panic on it, but a worker that stops anyway is
+// started again by next_result; the block that was in work is lost and the next one starts.
+
+use std::{collections::VecDeque, future::Future, sync::Arc};
+
+use futures::future::select_all;
+use libp2p::{gossipsub::MessageId, request_response::ResponseChannel, PeerId};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        Mutex, Semaphore,
+    },
+    task::{JoinError, JoinHandle},
+};
+
+use super::{
+    check_trx::handle_transactions,
+    config::CONFIG,
+    create_log::write_log,
+    reciept::insert_reciept,
+    recieved_block::store_block,
+    structures::{FullNodes, GossipMessage, Res, Transaction},
+};
+
+//gossip message that waits for its validation result
+#[derive(Clone)]
+pub struct GossipSource {
+    pub message_id: MessageId,
+    pub propagation_source: PeerId,
+}
+
+//where a block came from, its result goes back to it
+pub enum BlockOrigin {
+    Gossip(GossipSource),
+    Request { channel: ResponseChannel<Res> },
+    //gossip block that came while syncing, the relay is synced after all of them are stored
+    Syncing,
+}
+
+pub struct BlockJob {
+    pub str_msg: String,
+    pub gossip_message: GossipMessage,
+    pub origin: BlockOrigin,
+}
+
+pub struct BlockResult {
+    pub job: BlockJob,
+    pub result: Result<(), &'static str>,
+}
+
+//where a transaction came from, its result goes back to it
+pub enum TransactionOrigin {
+    Gossip(GossipSource),
+    //transaction of a client, it is published after it is checked
+    Request { channel: ResponseChannel<Res> },
+}
+
+pub struct TransactionResult {
+    pub message: String,
+    pub origin: TransactionOrigin,
+    pub result: Result<(), &'static str>,
+}
+
+pub enum StorageResult {
+    Block(Box<BlockResult>),
+    Transaction(TransactionResult),
+    //block worker stopped in the middle of a block and it is started again
+    BlockLost,
+    //response of a request that has read the database
+    Response {
+        channel: ResponseChannel<Res>,
+        response: Res,
+    },
+}
+
+//what a worker does with a transaction
+enum TransactionWork {
+    //check it and save its reciept
+    Check(TransactionOrigin),
+    //only save its pending reciept (transactions can't be checked while syncing)
+    PendingReciept,
+}
+
+struct TransactionJob {
+    message: String,
+    work: TransactionWork,
+}
+
+//a block with the leader of its round
+struct BlockWork {
+    job: BlockJob,
+    leader: String,
+    allow_genesis: bool,
+}
+
+//worker that has stopped
+enum StoppedWorker {
+    Block(Result<(), JoinError>),
+    Transaction(usize, Result<(), JoinError>),
+}
+
+pub struct StoragePipeline {
+    transactions: mpsc::Sender<TransactionJob>,
+    blocks: mpsc::Sender<BlockWork>,
+    results: mpsc::Receiver<StorageResult>,
+    //receivers and result sender are kept for starting workers again
+    tx_receiver: Arc<Mutex<mpsc::Receiver<TransactionJob>>>,
+    block_receiver: Arc<Mutex<mpsc::Receiver<BlockWork>>>,
+    result_sender: mpsc::Sender<StorageResult>,
+    reads: Arc<Semaphore>,
+    tx_workers: Vec<JoinHandle<()>>,
+    block_worker: JoinHandle<()>,
+    //blocks that wait for the result of the block in work
+    waiting_blocks: VecDeque<BlockJob>,
+    in_work: bool,
+}
+
+impl StoragePipeline {
+    //start the workers, they stop when the pipeline is dropped
+    pub fn spawn() -> Self {
+        let queue_size = CONFIG.storage_queue_size.max(1);
+        let (result_sender, results) = mpsc::channel::<StorageResult>(queue_size);
+        let (transactions, tx_receiver) = mpsc::channel::<TransactionJob>(queue_size);
+        let tx_receiver = Arc::new(Mutex::new(tx_receiver));
+        let tx_workers = (0..CONFIG.transaction_workers.max(1))
+            .map(|_| spawn_tx_worker(Arc::clone(&tx_receiver), result_sender.clone()))
+            .collect();
+
+        let (blocks, block_receiver) = mpsc::channel::<BlockWork>(1);
+        let block_receiver = Arc::new(Mutex::new(block_receiver));
+        let block_worker = spawn_block_worker(Arc::clone(&block_receiver), result_sender.clone());
+
+        Self {
+            transactions,
+            blocks,
+            results,
+            tx_receiver,
+            block_receiver,
+            result_sender,
+            reads: Arc::new(Semaphore::new(queue_size)),
+            tx_workers,
+            block_worker,
+            waiting_blocks: VecDeque::new(),
+            in_work: false,
+        }
+    }
+
+    //origin is given back if the transactions queue is full, so requesters can be answered
+    pub fn push_transaction(
+        &self,
+        message: String,
+        origin: TransactionOrigin,
+    ) -> Result<(), TransactionOrigin> {
+        let job = TransactionJob {
+            message,
+            work: TransactionWork::Check(origin),
+        };
+        match self.push_transaction_job(job) {
+            Ok(_) => Ok(()),
+            Err(TransactionWork::Check(origin)) => Err(origin),
+            Err(TransactionWork::PendingReciept) => unreachable!(),
+        }
+    }
+
+    //save the pending reciept of a transaction that came while syncing
+    pub fn push_pending_reciept(&self, message: String) -> bool {
+        self.push_transaction_job(TransactionJob {
+            message,
+            work: TransactionWork::PendingReciept,
+        })
+        .is_ok()
+    }
+
+    fn push_transaction_job(&self, job: TransactionJob) -> Result<(), TransactionWork> {
+        match self.transactions.try_send(job) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(job)) | Err(TrySendError::Closed(job)) => {
+                write_log("transactions queue is full! storage_pipeline");
+                Err(job.work)
+            }
+        }
+    }
+
+    //false if the blocks queue is full (the job is dropped, requesters get no response)
+    pub fn push_block(&mut self, job: BlockJob, leader: &str, fullnodes: &[FullNodes]) -> bool {
+        if self.waiting_blocks.len() >= CONFIG.storage_queue_size.max(1) {
+            write_log("blocks queue is full! storage_pipeline");
+            return false;
+        }
+        self.waiting_blocks.push_back(job);
+        self.next_block(leader, fullnodes);
+        true
+    }
+
+    //read the response of a request out of the swarm loop
+    //channel is given back if too many reads are in work, so requesters can be answered
+    pub fn push_read<F>(
+        &self,
+        channel: ResponseChannel<Res>,
+        read: F,
+    ) -> Result<(), ResponseChannel<Res>>
+    where
+        F: Future<Output = String> + Send + 'static,
+    {
+        let permit = match Arc::clone(&self.reads).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                write_log("too many reads of requests! storage_pipeline");
+                return Err(channel);
+            }
+        };
+        let result_sender = self.result_sender.clone();
+        tokio::spawn(async move {
+            let response = Res { res: read.await };
+            drop(permit);
+            let _ = result_sender
+                .send(StorageResult::Response { channel, response })
+                .await;
+        });
+        Ok(())
+    }
+
+    //result of a transaction or the block in work
+    //(next round of a block must be set before the next block starts)
+    //stopped workers are started again, BlockLost is returned if it was the block worker
+    pub async fn next_result(&mut self) -> Option<StorageResult> {
+        loop {
+            let stopped = tokio::select! {
+                result = self.results.recv() => return result,
+                joined = &mut self.block_worker => StoppedWorker::Block(joined),
+                (joined, index, _) = select_all(self.tx_workers.iter_mut()) => {
+                    StoppedWorker::Transaction(index, joined)
+                }
+            };
+            match stopped {
+                StoppedWorker::Block(joined) => {
+                    write_log(&format!(
+                        "block worker stopped: {:?}! storage_pipeline",
+                        joined.err()
+                    ));
+                    self.block_worker = spawn_block_worker(
+                        Arc::clone(&self.block_receiver),
+                        self.result_sender.clone(),
+                    );
+                    self.in_work = false;
+                    return Some(StorageResult::BlockLost);
+                }
+                StoppedWorker::Transaction(index, joined) => {
+                    write_log(&format!(
+                        "transaction worker stopped: {:?}! storage_pipeline",
+                        joined.err()
+                    ));
+                    self.tx_workers[index] =
+                        spawn_tx_worker(Arc::clone(&self.tx_receiver), self.result_sender.clone());
+                }
+            }
+        }
+    }
+
+    //start the next waiting block after the result of the last one
+    pub fn block_done(&mut self, leader: &str, fullnodes: &[FullNodes]) {
+        self.in_work = false;
+        self.next_block(leader, fullnodes);
+    }
+
+    fn next_block(&mut self, leader: &str, fullnodes: &[FullNodes]) {
+        if self.in_work {
+            return;
+        }
+        if let Some(job) = self.waiting_blocks.pop_front() {
+            let work = BlockWork {
+                job,
+                leader: leader.to_string(),
+                allow_genesis: fullnodes.len() < 2,
+            };
+            //capacity of blocks channel is one and only one block is in work
+            if self.blocks.try_send(work).is_ok() {
+                self.in_work = true;
+            } else {
+                write_log("block worker is not running! storage_pipeline");
+            }
+        }
+    }
+}
+
+//check and save transactions of the queue
+fn spawn_tx_worker(
+    tx_receiver: Arc<Mutex<mpsc::Receiver<TransactionJob>>>,
+    result_sender: mpsc::Sender<StorageResult>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let job = tx_receiver.lock().await.recv().await;
+            let job = match job {
+                Some(job) => job,
+                None => break,
+            };
+            let origin = match job.work {
+                TransactionWork::Check(origin) => origin,
+                TransactionWork::PendingReciept => {
+                    if let Ok(transaction) = serde_json::from_str::<Transaction>(&job.message) {
+                        insert_reciept(transaction, None, "pending".to_string(), String::new())
+                            .await;
+                    }
+                    continue;
+                }
+            };
+            let result = handle_transactions(job.message.clone()).await;
+            let tx_result = TransactionResult {
+                message: job.message,
+                origin,
+                result,
+            };
+            if result_sender
+                .send(StorageResult::Transaction(tx_result))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    })
+}
+
+//validate and store blocks one at a time
+fn spawn_block_worker(
+    block_receiver: Arc<Mutex<mpsc::Receiver<BlockWork>>>,
+    result_sender: mpsc::Sender<StorageResult>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let work = block_receiver.lock().await.recv().await;
+            let work = match work {
+                Some(work) => work,
+                None => break,
+            };
+            let result =
+                store_block(&work.job.gossip_message, &work.leader, work.allow_genesis).await;
+            let block_result = BlockResult {
+                job: work.job,
+                result,
+            };
+            if result_sender
+                .send(StorageResult::Block(Box::new(block_result)))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    })
+}
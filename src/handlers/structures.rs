@@ -14,18 +14,6 @@ pub struct Res {
     pub res: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ReqForReq {
-    pub peer: Vec<PeerId>,
-    pub req: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ResForReq {
-    pub peer: Vec<PeerId>,
-    pub res: Res,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ImSync {
     pub relay: PeerId,
@@ -174,13 +162,37 @@ pub struct GossipMessage {
     pub next_leader: String,
 }
 
-//users UTXO structure
+//all UTXOs of a user (response of rpc server)
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct UTXO {
     pub public_key: String,
     pub utxos: Vec<UtxoData>,
 }
 
+#[serde_as]
+//one unspent output in UTXOs collection, its output hash is the document id
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct UtxoRecord {
+    #[serde(rename = "_id")]
+    pub output_hash: String,
+    pub public_key: String,
+    pub transaction_hash: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub unspent: Decimal,
+    pub block_number: i64,
+}
+
+impl UtxoRecord {
+    pub fn into_data(self) -> UtxoData {
+        UtxoData {
+            transaction_hash: self.transaction_hash,
+            unspent: self.unspent,
+            output_hash: self.output_hash,
+            block_number: self.block_number,
+        }
+    }
+}
+
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Reciept {
@@ -215,3 +227,17 @@ pub struct GetGossipMsg {
     pub gossip: GossipMessage,
     pub propagation_source: PeerId,
 }
+
+//request for block headers of a range of block numbers (used by incremental syncing)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeadersReq {
+    pub headers_from: i64,
+    pub headers_to: i64,
+}
+
+//request for full blocks of a range of block numbers (used by incremental syncing)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlocksReq {
+    pub blocks_from: i64,
+    pub blocks_to: i64,
+}
@@ -1,49 +1,118 @@
 use std::time::Duration;
 
 use libp2p::{
-    gossipsub::IdentTopic, identity::Keypair, request_response::{cbor, ProtocolSupport}, swarm::NetworkBehaviour, Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder
+    autonat, dcutr, identify,
+    identity::Keypair,
+    kad::{self, store::MemoryStore},
+    mdns, relay,
+    request_response::{cbor, ProtocolSupport},
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour},
+    Multiaddr, PeerId, Swarm, SwarmBuilder,
 };
 
-use super::structures::{Req, Res};
+use super::{
+    ban_list::BanGuard,
+    chain_spec::CHAIN_SPEC,
+    circuit_relay::relay_config,
+    config::{Profile, CONFIG},
+    create_log::write_log,
+    peer_scoring::score_params,
+    structures::{Req, Res},
+};
 
 pub trait SwarmConf {
-    async fn new() -> (Swarm<CustomBehav>, PeerId);
+    async fn new(keypair: Keypair) -> (Swarm<CustomBehav>, PeerId);
 }
 
 #[derive(NetworkBehaviour)]
 pub struct CustomBehav {
     pub gossipsub: libp2p::gossipsub::Behaviour,
     pub req_res: cbor::Behaviour<Req, Res>,
+    //denies connections of banned peers and addresses (ban_list)
+    pub ban_guard: BanGuard,
+    //discovery of relays (discovery)
+    pub kademlia: kad::Behaviour<MemoryStore>,
+    //relays of the local network, off if mdns is not enabled in the config
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    //observed addresses of relay and listen addresses of other relays
+    pub identify: identify::Behaviour,
+    //confirms observed addresses by dial backs of other peers (reachability)
+    pub autonat: autonat::Behaviour,
+    //circuit relay v2 server (circuit_relay), off if it is not enabled in the config
+    pub relay: Toggle<relay::Behaviour>,
+    //circuits of other relays and their upgrade to direct connections by hole punching
+    pub relay_client: relay::client::Behaviour,
+    pub dcutr: dcutr::Behaviour,
 }
 
 impl SwarmConf for CustomBehav {
-    async fn new() -> (Swarm<Self>, PeerId) {
-        let relay_topic = IdentTopic::new("relay");
-        let clients_topic = IdentTopic::new("client");
+    async fn new(keypair: Keypair) -> (Swarm<Self>, PeerId) {
+        let relay_topic = CHAIN_SPEC.topic("relay");
+        let clients_topic = CHAIN_SPEC.topic("client");
 
-        //generate peer keys and peer id for network
-        let keypair = Keypair::generate_ecdsa();
+        //peer id for network
         let local_peer_id = PeerId::from(keypair.public());
 
         //gossip protocol config
         let privacy = libp2p::gossipsub::MessageAuthenticity::Signed(keypair.clone());
-        let gossip_cfg = libp2p::gossipsub::ConfigBuilder::default().build().unwrap();
+        //messages are forwarded only after report_message_validation_result accepts them
+        let gossip_cfg = libp2p::gossipsub::ConfigBuilder::default()
+            .validate_messages()
+            .build()
+            .unwrap();
         gossip_cfg.duplicate_cache_time();
-        let gossipsub = libp2p::gossipsub::Behaviour::new(privacy, gossip_cfg).unwrap();
+        let mut gossipsub = libp2p::gossipsub::Behaviour::new(privacy, gossip_cfg).unwrap();
+        let (score_params, score_thresholds) = score_params(&local_peer_id);
+        if let Err(e) = gossipsub.with_peer_score(score_params, score_thresholds) {
+            write_log(&format!(
+                "peer scoring config problem, scores are off: {}",
+                e
+            ));
+        }
         //request and response protocol config
         let req_res = cbor::Behaviour::<Req, Res>::new(
-            [(StreamProtocol::new("/mg/1.0"), ProtocolSupport::Full)],
+            [(CHAIN_SPEC.req_res_protocol(), ProtocolSupport::Full)],
             libp2p::request_response::Config::default(),
         );
 
-        //Definition of behavior
-        let mut behaviour = CustomBehav { gossipsub, req_res };
+        //kademlia of relays of this network
+        let mut kad_cfg = kad::Config::default();
+        kad_cfg.set_protocol_names(vec![CHAIN_SPEC.kad_protocol()]);
+        let mut kademlia =
+            kad::Behaviour::with_config(local_peer_id, MemoryStore::new(local_peer_id), kad_cfg);
+        //relays are public nodes, they answer kademlia requests without confirmed addresses
+        kademlia.set_mode(Some(kad::Mode::Server));
 
-        behaviour.gossipsub.subscribe(&relay_topic.clone()).unwrap();
-        behaviour
-            .gossipsub
-            .subscribe(&clients_topic.clone())
-            .unwrap();
+        let mdns = if CONFIG.mdns_enabled() {
+            match mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id) {
+                Ok(mdns) => Some(mdns),
+                Err(e) => {
+                    write_log(&format!("mdns problem, it is off: {}", e));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let identify = identify::Behaviour::new(
+            identify::Config::new(CHAIN_SPEC.identify_protocol(), keypair.public())
+                .with_agent_version(format!("relay-node/{}", env!("CARGO_PKG_VERSION"))),
+        );
+        //relays of devnets confirm private addresses of each other
+        let autonat_cfg = autonat::Config {
+            only_global_ips: CONFIG.profile != Profile::Devnet,
+            ..Default::default()
+        };
+        let autonat = autonat::Behaviour::new(local_peer_id, autonat_cfg);
+
+        let relay = CONFIG
+            .circuit_relay
+            .enabled
+            .then(|| relay::Behaviour::new(local_peer_id, relay_config()));
+
+        gossipsub.subscribe(&relay_topic.clone()).unwrap();
+        gossipsub.subscribe(&clients_topic.clone()).unwrap();
 
         //config swarm
         let swarm_config = libp2p::swarm::Config::with_tokio_executor()
@@ -66,7 +135,24 @@ impl SwarmConf for CustomBehav {
             )
             .await
             .unwrap()
-            .with_behaviour(|_key| behaviour)
+            .with_relay_client(
+                (libp2p::tls::Config::new, libp2p::noise::Config::new),
+                libp2p::yamux::Config::default,
+            )
+            .unwrap()
+            //Definition of behavior
+            .with_behaviour(|_key, relay_client| CustomBehav {
+                gossipsub,
+                req_res,
+                ban_guard: BanGuard::default(),
+                kademlia,
+                mdns: Toggle::from(mdns),
+                identify,
+                autonat,
+                relay: Toggle::from(relay),
+                relay_client,
+                dcutr: dcutr::Behaviour::new(local_peer_id),
+            })
             .unwrap()
             .with_swarm_config(|_conf| swarm_config)
             .build();
@@ -1,244 +1,127 @@
-use async_std::stream::StreamExt;
 use mongodb::{
     bson::{doc, from_document, Document},
-    options::FindOneOptions,
     Collection,
 };
 use std::{
     fs::{self, File},
-    io::{BufReader, Read, Write},
-    process::Command,
+    io::BufReader,
 };
 
 use super::{
-    create_log::write_log, db_connection::blockchain_db, recieved_block::create_hash,
+    block_apply::BLOCK_WRITES,
+    chain_spec::{CHAIN_SPEC, GENESIS_PREVHASH},
+    create_log::write_log,
+    db_connection::blockchain_db,
+    download::download_from_relays,
+    recieved_block::{check_block_sign, create_hash},
+    snapshot::{extract_snapshot, sync_archive_path},
     structures::Block,
 };
 
-pub async fn syncing(dialed_addr: String) -> Result<(), ()> {
+pub async fn syncing(dialed_addrs: Vec<String>) -> Result<(), ()> {
     match blockchain_db().await {
         Ok(db) => {
-            let trim_addr = dialed_addr.trim_start_matches("/ip4/");
-            let split_addr = trim_addr.split("/").next();
-            match split_addr {
-                Some(addr) => {
-                    let blockchain_addr =
-                        format!("http://{}:33369/blockchain/blockchain.zip", addr);
-                    write_log(&format!("syncing with {}", blockchain_addr));
-
-                    //---------------------------------------------------------
-                    //remove blockchain.zip in home if exist
-                    let zip_exist = fs::metadata("/home/blockchain.zip");
-                    if zip_exist.is_ok() {
-                        let rm_zip = Command::new("rm").arg("/home/blockchain.zip").status();
-                        write_log(&format!(
-                            "remove blockechain.zip:{}",
-                            rm_zip.unwrap().to_string()
-                        ));
-                    }
-                    let mut blockchain_output = fs::File::create("/home/blockchain.zip").unwrap();
-
-                    //---------------------------------------------------------
-                    //get latest version of blockchain in zip format
-                    match reqwest::get(blockchain_addr).await {
-                        Ok(res) => {
-                            write_log("get response from connected relay");
-                            let mut body = res.bytes_stream();
-
-                            //write response to blockchain.zip file
-                            while let Some(item) = body.next().await {
-                                let chunk = item.unwrap();
-                                blockchain_output.write_all(&chunk).unwrap();
-                            }
-
-                            //---------------------------------------------------------
-                            //remove etc in home if exist
-                            let etc_exist = fs::metadata("/home/etc");
-                            if etc_exist.is_ok() {
-                                let rm_etc = Command::new("rm").arg("-r").arg("/home/etc").status();
-                                write_log(&format!(
-                                    "remove etc in home direction: {}",
-                                    rm_etc.unwrap().to_string()
-                                ));
-                            }
-
-                            //---------------------------------------------------------
-                            //unzip blockchain.zip file and create files from it
-                            let blockchain_file = File::open("/home/blockchain.zip").unwrap();
-                            fs::create_dir_all("/home/etc/dump/Blockchain").unwrap();
-                            let mut archive = zip::ZipArchive::new(blockchain_file).unwrap();
-                            for i in 0..archive.len() {
-                                let mut file = archive.by_index(i).unwrap();
-                                if file.is_file() {
-                                    let mut output =
-                                        fs::File::create(&format!("/home/{}", file.name()))
-                                            .unwrap();
-                                    let mut file_bytes = Vec::new();
-                                    file.read_to_end(&mut file_bytes).unwrap();
-                                    output.write_all(&file_bytes).unwrap();
-                                }
-                            }
-
-                            //---------------------------------------------------------
-                            //open and read reciepts.bson file and insert it to database
-                            let reciept_bson =
-                                File::open("/home/etc/dump/Blockchain/reciept.bson").unwrap();
-                            let mut reciept_reader = BufReader::new(reciept_bson);
-
-                            let reciept_coll: Collection<Document> = db.collection("reciept");
-                            let sort = doc! {"_id": -1};
-                            let option = FindOneOptions::builder().sort(sort).build();
-                            match reciept_coll.find_one(None, option.clone()).await {
-                                Ok(doc) => {
-                                    if doc.is_some() {
-                                        match reciept_coll.delete_many(doc! {}, None).await {
-                                            Ok(_) => {
-                                                write_log("delete old reciept collection");
-                                                while let Ok(document) =
-                                                    Document::from_reader(&mut reciept_reader)
-                                                {
-                                                    reciept_coll
-                                                        .insert_one(document, None)
-                                                        .await
-                                                        .unwrap();
-                                                }
-                                                write_log("insert new reciept collection");
-                                            }
-                                            Err(_) => {
-                                                write_log("delete reciept collection error");
-                                                return Err(());
-                                            }
-                                        }
-                                    } else {
-                                        while let Ok(doc) =
-                                            Document::from_reader(&mut reciept_reader)
-                                        {
-                                            reciept_coll.insert_one(doc, None).await.unwrap();
-                                        }
-                                    }
-                                }
-                                Err(_) => {
-                                    while let Ok(doc) = Document::from_reader(&mut reciept_reader) {
-                                        reciept_coll.insert_one(doc, None).await.unwrap();
-                                    }
-                                }
-                            }
-
-                            //---------------------------------------------------------
-                            //open and read UTXOs.bson file and insert it to database
-                            let utxo_bson =
-                                File::open("/home/etc/dump/Blockchain/UTXOs.bson").unwrap();
-                            let mut utxo_reader = BufReader::new(utxo_bson);
-
-                            let utxo_coll: Collection<Document> = db.collection("UTXOs");
-
-                            match utxo_coll.find_one(None, option.clone()).await {
-                                Ok(doc) => {
-                                    if doc.is_some() {
-                                        match utxo_coll.delete_many(doc! {}, None).await {
-                                            Ok(_) => {
-                                                write_log("delete old utxo collection");
-                                                while let Ok(document) =
-                                                    Document::from_reader(&mut utxo_reader)
-                                                {
-                                                    utxo_coll
-                                                        .insert_one(document, None)
-                                                        .await
-                                                        .unwrap();
-                                                }
-                                                write_log("insert new utxo collection");
-                                            }
-                                            Err(_) => {
-                                                write_log("delete utxo collection error");
-                                                return Err(());
-                                            }
-                                        }
-                                    } else {
-                                        while let Ok(document) =
-                                            Document::from_reader(&mut utxo_reader)
-                                        {
-                                            utxo_coll.insert_one(document, None).await.unwrap();
-                                        }
-                                    }
-                                }
-                                Err(_) => {
-                                    while let Ok(document) = Document::from_reader(&mut utxo_reader)
-                                    {
-                                        utxo_coll.insert_one(document, None).await.unwrap();
-                                    }
-                                }
-                            }
+            //---------------------------------------------------------
+            //get latest verified version of blockchain in zip format from the relays
+            let signed = match download_from_relays(&dialed_addrs).await {
+                Some(signed) => signed,
+                None => {
+                    write_log("could not get blockchain.zip from any relay");
+                    return Err(());
+                }
+            };
 
-                            //---------------------------------------------------------
-                            //open and read Blocks.bson file and insert it to database
-                            let blocks_bson =
-                                File::open("/home/etc/dump/Blockchain/Blocks.bson").unwrap();
-                            let blocks_reader = BufReader::new(blocks_bson);
+            //---------------------------------------------------------
+            //remove etc in home if exist
+            if fs::metadata("/home/etc").is_ok() {
+                if let Err(e) = fs::remove_dir_all("/home/etc") {
+                    write_log(&format!("removing etc in home direction problem: {}", e));
+                    return Err(());
+                }
+            }
 
-                            let block_coll: Collection<Document> = db.collection("Blocks");
-                            match block_coll.find_one(None, option.clone()).await {
-                                Ok(doc) => {
-                                    if doc.is_some() {
-                                        match block_coll.delete_many(doc! {}, None).await {
-                                            Ok(_) => {
-                                                write_log("delete old block collection");
-                                                match insert_blocks(blocks_reader, block_coll).await
-                                                {
-                                                    Ok(_) => {
-                                                        write_log("insert new block collection");
-                                                    }
-                                                    Err(_) => {
-                                                        write_log("insert block collection error");
-                                                        return Err(());
-                                                    }
-                                                }
-                                            }
-                                            Err(_) => {
-                                                write_log("delete block collection error");
-                                                return Err(());
-                                            }
-                                        }
-                                    } else {
-                                        match insert_blocks(blocks_reader, block_coll).await {
-                                            Ok(_) => {}
-                                            Err(_) => {
-                                                write_log("insert block collection error");
-                                                return Err(());
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(_) => match insert_blocks(blocks_reader, block_coll).await {
-                                    Ok(_) => {}
-                                    Err(_) => {
-                                        write_log("insert block collection error");
-                                        return Err(());
-                                    }
-                                },
-                            }
+            //---------------------------------------------------------
+            //check blockchain.zip with its manifest and extract its files
+            match extract_snapshot(sync_archive_path(), "/home/etc/dump/Blockchain") {
+                Ok(manifest) if manifest == signed.info.manifest => {
+                    write_log(&format!("snapshot of block {} extracted", manifest.height))
+                }
+                Ok(_) => {
+                    write_log("snapshot manifest is not equal to the signed manifest");
+                    return Err(());
+                }
+                Err(e) => {
+                    write_log(&format!("snapshot extracting problem: {}", e));
+                    return Err(());
+                }
+            }
 
-                            return Ok(());
-                        }
-                        Err(e) => {
-                            write_log(&format!(
-                                "get blockchain.zip from a rpc sserver problem:{e}"
-                            ));
-                            return Err(());
-                        }
-                    }
+            //snapshots and the block worker don't see the database in the middle of importing
+            let _writes = BLOCK_WRITES.lock().await;
+
+            //---------------------------------------------------------
+            //replace reciepts and UTXOs with reciept.bson and UTXOs.bson
+            let reciept_coll: Collection<Document> = db.collection("reciept");
+            import_collection(&reciept_coll, "/home/etc/dump/Blockchain/reciept.bson").await?;
+            let utxo_coll: Collection<Document> = db.collection("UTXOs");
+            import_collection(&utxo_coll, "/home/etc/dump/Blockchain/UTXOs.bson").await?;
+
+            //---------------------------------------------------------
+            //open and read Blocks.bson file and insert it to database
+            let blocks_bson = match File::open("/home/etc/dump/Blockchain/Blocks.bson") {
+                Ok(file) => file,
+                Err(e) => {
+                    write_log(&format!("opening Blocks.bson problem: {}", e));
+                    return Err(());
                 }
-                None => {
-                    write_log("spliting address in syncing.rs problem");
+            };
+            let blocks_reader = BufReader::new(blocks_bson);
+
+            let block_coll: Collection<Document> = db.collection("Blocks");
+            if block_coll.delete_many(doc! {}, None).await.is_err() {
+                write_log("delete block collection error");
+                return Err(());
+            }
+            match insert_blocks(blocks_reader, block_coll).await {
+                Ok(_) => write_log("insert new block collection"),
+                Err(_) => {
+                    write_log("insert block collection error");
                     return Err(());
                 }
             }
+
+            Ok(())
         }
-        Err(_e) => return Err(()),
+        Err(_e) => Err(()),
     }
 }
 
+//replace documents of a collection with documents of a .bson file of the snapshot
+async fn import_collection(coll: &Collection<Document>, path: &str) -> Result<(), ()> {
+    let bson_file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            write_log(&format!("opening {} problem: {}", path, e));
+            return Err(());
+        }
+    };
+    let mut reader = BufReader::new(bson_file);
+    if coll.delete_many(doc! {}, None).await.is_err() {
+        write_log(&format!("delete {} collection error", coll.name()));
+        return Err(());
+    }
+    while let Ok(document) = Document::from_reader(&mut reader) {
+        if let Err(e) = coll.insert_one(document, None).await {
+            write_log(&format!("insert {} collection error: {}", coll.name(), e));
+            return Err(());
+        }
+    }
+    write_log(&format!("insert new {} collection", coll.name()));
+    Ok(())
+}
 
 //insert blocks of blockchain.zip that recieved from rpc server
+//(only used in snapshot sync mode, UTXOs and reciepts of the zip are trusted)
 async fn insert_blocks(
     mut blocks_reader: BufReader<File>,
     block_coll: Collection<Document>,
@@ -246,23 +129,31 @@ async fn insert_blocks(
     let mut prev_hash = String::new();
 
     while let Ok(doc) = Document::from_reader(&mut blocks_reader) {
-        let block: Block = from_document(doc.clone()).unwrap();
+        let block: Block = from_document(doc.clone()).map_err(|_| ())?;
+        if check_block_sign(&block).is_err() {
+            return Err(());
+        }
 
-        if block.header.prevhash != "This block is Genesis".to_string()
-            && block.header.prevhash == prev_hash
+        if block.header.prevhash == GENESIS_PREVHASH
+            && !CHAIN_SPEC.is_genesis(&block.header.blockhash)
         {
-            prev_hash.clear();
-            let str_block_body = serde_json::to_string(&block.body).unwrap();
-            prev_hash.push_str(&create_hash(str_block_body));
-            block_coll.insert_one(doc, None).await.unwrap();
-        } else if block.header.prevhash == "This block is Genesis".to_string() {
-            prev_hash.clear();
-            let str_block_body = serde_json::to_string(&block.body).unwrap();
-            prev_hash.push_str(&create_hash(str_block_body));
-            block_coll.insert_one(doc, None).await.unwrap();
+            write_log("genesis of blockchain.zip is not the genesis of chain spec");
+            return Err(());
+        }
+
+        //genesis is the first block and every other block is linked to the block before it
+        let linked = if prev_hash.is_empty() {
+            block.header.prevhash == GENESIS_PREVHASH
         } else {
+            block.header.prevhash == prev_hash
+        };
+        let str_block_body = serde_json::to_string(&block.body).map_err(|_| ())?;
+        if !linked || create_hash(str_block_body) != block.header.blockhash {
             return Err(());
         }
+        prev_hash.clear();
+        prev_hash.push_str(&block.header.blockhash);
+        block_coll.insert_one(doc, None).await.map_err(|_| ())?;
     }
     Ok(())
 }
@@ -0,0 +1,117 @@
+// UTXOs collection keeps one document per unspent output (UtxoRecord):
+//   _id              - output hash, so finding or spending an outpoint is one indexed lookup
+//   public_key       - owner of the output (indexed for /utxo and /autxo)
+//   transaction_hash - transaction (or coinbase) that made the output
+//   unspent          - value of the output as string
+//   block_number     - block that confirmed the output
+//
+// Older relays kept one document per public key with an embedded `utxos` array, these documents
+// are converted to records by migration 1 (migrations.rs).
+
+use futures::StreamExt;
+use mongodb::{
+    bson::{doc, from_document, to_document, Document},
+    options::{FindOptions, ReplaceOptions},
+    Collection,
+};
+
+use super::structures::{UtxoRecord, UTXO};
+
+//find an unspent output by its hash
+pub async fn find_utxo(
+    utxos_coll: &Collection<Document>,
+    output_hash: &str,
+) -> Result<Option<UtxoRecord>, ()> {
+    match utxos_coll.find_one(doc! {"_id": output_hash}, None).await {
+        Ok(Some(doc)) => match from_document::<UtxoRecord>(doc) {
+            Ok(record) => Ok(Some(record)),
+            Err(_) => Err(()),
+        },
+        Ok(None) => Ok(None),
+        Err(_) => Err(()),
+    }
+}
+
+//all unspent outputs of a public key
+pub async fn user_utxos(utxos_coll: &Collection<Document>, public_key: &str) -> Result<UTXO, ()> {
+    let option = FindOptions::builder()
+        .sort(doc! {"block_number": 1})
+        .build();
+    match utxos_coll
+        .find(doc! {"public_key": public_key}, option)
+        .await
+    {
+        Ok(mut cursor) => {
+            let mut utxos = Vec::new();
+            while let Some(doc) = cursor.next().await {
+                match doc.map(from_document::<UtxoRecord>) {
+                    Ok(Ok(record)) => utxos.push(record.into_data()),
+                    _ => return Err(()),
+                }
+            }
+            Ok(UTXO {
+                public_key: public_key.to_string(),
+                utxos,
+            })
+        }
+        Err(_) => Err(()),
+    }
+}
+
+//insert a new unspent output (inserting the same output again doesn't change anything)
+pub async fn add_utxo(utxos_coll: &Collection<Document>, record: UtxoRecord) -> Result<(), ()> {
+    let filter = doc! {"_id": &record.output_hash};
+    let record_doc = to_document(&record).map_err(|_| ())?;
+    let option = ReplaceOptions::builder().upsert(true).build();
+    match utxos_coll.replace_one(filter, record_doc, option).await {
+        Ok(_) => Ok(()),
+        Err(_) => Err(()),
+    }
+}
+
+//remove an output of the owner from UTXOs, returns false if it was not unspent
+pub async fn spend_utxo(
+    utxos_coll: &Collection<Document>,
+    public_key: &str,
+    output_hash: &str,
+) -> Result<bool, ()> {
+    let filter = doc! {"_id": output_hash, "public_key": public_key};
+    match utxos_coll.delete_one(filter, None).await {
+        Ok(result) => Ok(result.deleted_count == 1),
+        Err(_) => Err(()),
+    }
+}
+
+//convert documents of the old model (one document per public key) to one record per output
+pub async fn convert_legacy_utxos(utxos_coll: &Collection<Document>) -> Result<u64, ()> {
+    let mut converted = 0;
+    let mut cursor = match utxos_coll
+        .find(doc! {"utxos": {"$exists": true}}, None)
+        .await
+    {
+        Ok(cursor) => cursor,
+        Err(_) => return Err(()),
+    };
+    while let Some(doc) = cursor.next().await {
+        let legacy: UTXO = match doc.map(from_document) {
+            Ok(Ok(legacy)) => legacy,
+            _ => return Err(()),
+        };
+        for data in legacy.utxos {
+            let record = UtxoRecord {
+                output_hash: data.output_hash,
+                public_key: legacy.public_key.clone(),
+                transaction_hash: data.transaction_hash,
+                unspent: data.unspent,
+                block_number: data.block_number,
+            };
+            add_utxo(utxos_coll, record).await?;
+        }
+        let legacy_filter = doc! {"public_key": &legacy.public_key, "utxos": {"$exists": true}};
+        if utxos_coll.delete_one(legacy_filter, None).await.is_err() {
+            return Err(());
+        }
+        converted += 1;
+    }
+    Ok(converted)
+}
@@ -1,7 +1,16 @@
+use std::env;
+use std::process;
 use std::sync::Arc;
 use std::sync::Mutex;
+use libp2p::identity::Keypair;
+mod commands;
 mod handlers;
+use handlers::pruning::pruner;
 use handlers::run_relay::run;
+use handlers::snapshot_scheduler::snapshot_scheduler;
+use handlers::chain_spec::trust_stored_genesis;
+use handlers::create_log::write_log;
+use handlers::migrations::{check_reindex_mark, migrate_database};
 use handlers::swarm_config::CustomBehav;
 mod rpc;
 use handlers::swarm_config::SwarmConf;
@@ -9,11 +18,37 @@ use rpc::handle_requests;
 
 #[tokio::main]
 async fn main() {
-    let swarm_config = CustomBehav::new().await;
+    let args: Vec<String> = env::args().skip(1).collect();
+    //relay can't work with a database that has an unknown layout
+    //(benchmarks don't use the database)
+    if !commands::is_benchmark(&args) {
+        if let Err(e) = migrate_database().await {
+            write_log(&format!("database migration problem: {}", e));
+            return;
+        }
+        if let Err(e) = trust_stored_genesis().await {
+            write_log(&format!("reading genesis block problem: {}", e));
+            return;
+        }
+    }
+    //commands like reset-db run instead of the relay
+    if !args.is_empty() {
+        process::exit(commands::run_command(&args).await);
+    }
+    //UTXOs and reciepts are not made from all of the blocks after a failed block apply
+    if let Err(e) = check_reindex_mark().await {
+        write_log(&e);
+        return;
+    }
+    //generate peer keys, they are the identity of relay in network and for signing snapshots
+    let keypair = Keypair::generate_ecdsa();
+    let swarm_config = CustomBehav::new(keypair.clone()).await;
     let local_peer_id = swarm_config.1;
     let swarm = Arc::new(Mutex::new(swarm_config.0));
-    let (_, _) = tokio::join!(
+    let (_, _, _, _) = tokio::join!(
         run(Arc::clone(&swarm), local_peer_id),
-        handle_requests()
+        handle_requests(keypair),
+        snapshot_scheduler(),
+        pruner()
     );
 }
@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+
+use axum::{extract::ConnectInfo, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::handlers::ban_list::{
+    add_ban, bans, expiry, import_bans, remove_ban, set_ban_expiry, Ban, BanTarget,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BanReq {
+    //peer id, IP address or CIDR
+    pub target: String,
+    //none: no expiry
+    pub minutes: Option<u64>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BansRes {
+    pub bans: Vec<Ban>,
+    pub status: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminRes {
+    pub status: String,
+}
+
+fn admin_res(status: &str) -> Json<AdminRes> {
+    Json(AdminRes {
+        status: status.to_string(),
+    })
+}
+
+//admin requests of ban list are answered only on the machine of the relay
+fn not_admin() -> &'static str {
+    "Admin requests are only accepted from localhost!"
+}
+
+pub async fn handle_list_bans(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> Json<BansRes> {
+    if !addr.ip().is_loopback() {
+        return Json(BansRes {
+            bans: Vec::new(),
+            status: not_admin().to_string(),
+        });
+    }
+    Json(BansRes {
+        bans: bans(),
+        status: "".to_string(),
+    })
+}
+
+pub async fn handle_add_ban(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<BanReq>,
+) -> Json<AdminRes> {
+    if !addr.ip().is_loopback() {
+        return admin_res(not_admin());
+    }
+    match req.target.parse::<BanTarget>() {
+        Ok(target) => {
+            add_ban(Ban {
+                target,
+                until: expiry(req.minutes),
+                reason: req.reason,
+            });
+            admin_res("")
+        }
+        Err(e) => admin_res(&e),
+    }
+}
+
+pub async fn handle_remove_ban(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<BanReq>,
+) -> Json<AdminRes> {
+    if !addr.ip().is_loopback() {
+        return admin_res(not_admin());
+    }
+    match req.target.parse::<BanTarget>() {
+        Ok(target) if remove_ban(&target) => admin_res(""),
+        Ok(_) => admin_res("Ban not found!"),
+        Err(e) => admin_res(&e),
+    }
+}
+
+pub async fn handle_ban_expiry(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<BanReq>,
+) -> Json<AdminRes> {
+    if !addr.ip().is_loopback() {
+        return admin_res(not_admin());
+    }
+    match req.target.parse::<BanTarget>() {
+        Ok(target) if set_ban_expiry(&target, expiry(req.minutes)) => admin_res(""),
+        Ok(_) => admin_res("Ban not found!"),
+        Err(e) => admin_res(&e),
+    }
+}
+
+//bans.json of this relay, it can be imported in other relays
+pub async fn handle_export_bans(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> Json<Vec<Ban>> {
+    if !addr.ip().is_loopback() {
+        return Json(Vec::new());
+    }
+    Json(bans())
+}
+
+pub async fn handle_import_bans(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(imported): Json<Vec<Ban>>,
+) -> Json<AdminRes> {
+    if !addr.ip().is_loopback() {
+        return admin_res(not_admin());
+    }
+    let count = import_bans(imported);
+    admin_res(&format!("{} bans imported", count))
+}
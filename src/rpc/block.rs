@@ -4,7 +4,7 @@ use axum::{
 };
 
 use mongodb::{bson::{doc, from_document, Document}, Collection};
-use crate::handlers::{db_connection::blockchain_db, structures::Block};
+use crate::handlers::{db_connection::blockchain_db, pruning::is_pruned_block, structures::Block};
 
 use super::server::{BlockReq, BlockRes};
 
@@ -23,6 +23,12 @@ pub async fn handle_block(extract::Json(block_req): extract::Json<BlockReq>) ->
                     });
                 }
                 None => {
+                    if is_pruned_block(block_req.block_number).await {
+                        return Json(BlockRes {
+                            block: None,
+                            status: "pruned".to_string(),
+                        });
+                    }
                     return Json(BlockRes {
                         block: None,
                         status: "Block not found!".to_string(),
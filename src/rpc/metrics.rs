@@ -0,0 +1,7 @@
+use axum::Json;
+
+use crate::handlers::metrics::{metrics, Metrics};
+
+pub async fn handle_metrics() -> Json<Metrics> {
+    Json(metrics())
+}
@@ -4,5 +4,9 @@ mod transaction;
 mod utxo;
 mod reciept;
 mod block;
+mod metrics;
+mod reachability;
+mod snapshot;
+mod bans;
 pub mod swarm_cfg;
 pub mod one_utxo;
\ No newline at end of file
@@ -1,16 +1,15 @@
 use std::str::FromStr;
 
 use axum::{extract, Json};
-use mongodb::{
-    bson::{doc, from_document, Document},
-    Collection,
-};
+use mongodb::{bson::Document, Collection};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 use crate::handlers::{
+    chain_spec::CHAIN_SPEC,
     db_connection::blockchain_db,
     structures::{UtxoData, UTXO},
+    utxo_store::user_utxos,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,21 +31,9 @@ pub async fn a_utxo(extract::Json(request): extract::Json<ReqBody>) -> Json<ResB
     match blockchain_db().await {
         Ok(db) => {
             let utxos_coll: Collection<Document> = db.collection("UTXOs");
-            let filter = doc! {"public_key": request.public_key.clone()};
-            match utxos_coll.find_one(filter, None).await {
-                Ok(doc) => match doc {
-                    Some(document) => set_response_utxos(document, request),
-                    None => {
-                        let res = ResBody {
-                            public_key: request.public_key,
-                            utxo_data: Vec::new(),
-                            status: "error".to_string(),
-                            description: "There is no any utxo with this public key".to_string(),
-                        };
-                        return Json(res);
-                    }
-                },
-                Err(_) => {
+            match user_utxos(&utxos_coll, &request.public_key).await {
+                Ok(utxo) if !utxo.utxos.is_empty() => set_response_utxos(utxo, request),
+                _ => {
                     let res = ResBody {
                         public_key: request.public_key,
                         utxo_data: Vec::new(),
@@ -69,10 +56,9 @@ pub async fn a_utxo(extract::Json(request): extract::Json<ReqBody>) -> Json<ResB
     }
 }
 
-fn set_response_utxos(document: Document, request: ReqBody) -> Json<ResBody> {
-    let utxo: UTXO = from_document(document).unwrap();
+fn set_response_utxos(utxo: UTXO, request: ReqBody) -> Json<ResBody> {
     let value = Decimal::from_str(&request.value).unwrap(); //convert string of requst's value to Decimal
-    let fee = value * Decimal::from_str("0.01").unwrap();
+    let fee = CHAIN_SPEC.fee(value);
     let mut all_utxos_data = Vec::new();
     let mut utxo_data = Vec::new();
     for data in utxo.utxos {
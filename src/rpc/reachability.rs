@@ -0,0 +1,7 @@
+use axum::Json;
+
+use crate::handlers::reachability::{reachability, Reachability};
+
+pub async fn handle_reachability() -> Json<Reachability> {
+    Json(reachability())
+}
@@ -6,18 +6,33 @@ use tower::limit::ConcurrencyLimitLayer;
 
 use axum::{
     http::Method,
-    routing::post,
-    Router,
+    routing::{get, post},
+    Extension, Router,
 };
+use libp2p::identity::Keypair;
 use tower_http::{
     cors::{AllowHeaders, Any, CorsLayer},
-    services::ServeDir,
+    services::ServeFile,
 };
 
-use crate::{handlers::create_log::write_log, handlers::structures::Block};
+use crate::{
+    handlers::create_log::write_log, handlers::snapshot::snapshot_path,
+    handlers::structures::Block,
+};
 
 use super::{
-    block::handle_block, one_utxo::a_utxo, reciept::{handle_reciept, handle_user_reciepts}, transaction::handle_transaction, utxo::handle_utxo
+    bans::{
+        handle_add_ban, handle_ban_expiry, handle_export_bans, handle_import_bans,
+        handle_list_bans, handle_remove_ban,
+    },
+    block::handle_block,
+    metrics::handle_metrics,
+    one_utxo::a_utxo,
+    reachability::handle_reachability,
+    reciept::{handle_reciept, handle_user_reciepts},
+    snapshot::{handle_manifest, handle_snapshot_status},
+    transaction::handle_transaction,
+    utxo::handle_utxo,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -75,7 +90,17 @@ pub struct TxRes {
     pub description: String,
 }
 
-pub async fn handle_requests() {
+fn admin_routes() -> Router {
+    Router::new()
+        .route("/admin/bans", get(handle_list_bans))
+        .route("/admin/bans/add", post(handle_add_ban))
+        .route("/admin/bans/remove", post(handle_remove_ban))
+        .route("/admin/bans/expiry", post(handle_ban_expiry))
+        .route("/admin/bans/export", get(handle_export_bans))
+        .route("/admin/bans/import", post(handle_import_bans))
+}
+
+pub async fn handle_requests(keypair: Keypair) {
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST])
         .allow_origin(Any)
@@ -87,12 +112,21 @@ pub async fn handle_requests() {
         .route("/urec", post(handle_user_reciepts))
         .route("/block", post(handle_block))
         .route("/autxo", post(a_utxo))
+        .route("/snapshot/manifest", get(handle_manifest))
+        .route("/snapshot/status", get(handle_snapshot_status))
+        .route("/metrics", get(handle_metrics))
+        .route("/reachability", get(handle_reachability))
+        .layer(Extension(keypair))
         .layer(cors)
+        //admin of ban list, only from localhost and without cors so pages can't call it
+        .merge(admin_routes())
         .layer(ConcurrencyLimitLayer::new(100))
-        .nest_service("/blockchain", ServeDir::new("/home"));
+        //only the snapshot archive is served as a static file
+        .route_service("/snapshot/blockchain.zip", ServeFile::new(snapshot_path()))
+        .route_service("/blockchain/blockchain.zip", ServeFile::new(snapshot_path()));
     let addr = SocketAddr::from(([0, 0, 0, 0], 33369));
 
-    match axum_server::bind(addr).serve(app.into_make_service()).await {
+    match axum_server::bind(addr).serve(app.into_make_service_with_connect_info::<SocketAddr>()).await {
         Ok(_) => {}
         Err(e) => write_log(&format!("error from RPC server:\n{}", e)),
     }
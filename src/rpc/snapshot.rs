@@ -0,0 +1,38 @@
+use std::fs;
+
+use axum::{Extension, Json};
+use libp2p::identity::Keypair;
+
+use crate::handlers::{
+    snapshot::{manifest_path, sign_manifest, ManifestRes, SnapshotInfo},
+    snapshot_scheduler::{snapshot_status, SnapshotStatus},
+};
+
+pub async fn handle_manifest(Extension(keypair): Extension<Keypair>) -> Json<ManifestRes> {
+    match fs::read_to_string(manifest_path()) {
+        Ok(str_info) => match serde_json::from_str::<SnapshotInfo>(&str_info) {
+            Ok(info) => match sign_manifest(info, &keypair) {
+                Ok(signed) => Json(ManifestRes {
+                    signed: Some(signed),
+                    status: "".to_string(),
+                }),
+                Err(_) => Json(ManifestRes {
+                    signed: None,
+                    status: "Relay has problem! try with anothers.".to_string(),
+                }),
+            },
+            Err(_) => Json(ManifestRes {
+                signed: None,
+                status: "Snapshot manifest problem! try with anothers.".to_string(),
+            }),
+        },
+        Err(_) => Json(ManifestRes {
+            signed: None,
+            status: "Snapshot not found!".to_string(),
+        }),
+    }
+}
+
+pub async fn handle_snapshot_status() -> Json<SnapshotStatus> {
+    Json(snapshot_status())
+}
@@ -4,10 +4,12 @@ use libp2p::{
     identity::Keypair,
     request_response::{cbor, ProtocolSupport},
     swarm::NetworkBehaviour,
-    Multiaddr, StreamProtocol, Swarm, SwarmBuilder,
+    Multiaddr, Swarm, SwarmBuilder,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::handlers::chain_spec::CHAIN_SPEC;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Req {
     pub req: String,
@@ -34,7 +36,7 @@ impl SwarmConf for CostumBehav {
 
         //request and response protocol config
         let req_res = cbor::Behaviour::<Req, Res>::new(
-            [(StreamProtocol::new("/mg/1.0"), ProtocolSupport::Full)],
+            [(CHAIN_SPEC.req_res_protocol(), ProtocolSupport::Full)],
             libp2p::request_response::Config::default(),
         );
 
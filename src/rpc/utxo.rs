@@ -1,12 +1,10 @@
 use axum::{extract, Json};
-use mongodb::{
-    bson::{doc, from_document, Document},
-    Collection,
-};
+use mongodb::{bson::Document, Collection};
 
 use crate::handlers::{
     db_connection::blockchain_db,
     structures::UTXO,
+    utxo_store::user_utxos,
 };
 
 use super::server::ReqForUtxo;
@@ -15,19 +13,14 @@ pub async fn handle_utxo(extract::Json(utxo_req): extract::Json<ReqForUtxo>) ->
     match blockchain_db().await {
         Ok(db) => {
             let utxo_coll: Collection<Document> = db.collection("UTXOs");
-            let filter = doc! {"public_key": utxo_req.public_key.clone()};
-            let documnet = utxo_coll.find_one(filter, None).await.unwrap();
-            match documnet {
-                Some(doc) => {
-                    let utxo: UTXO = from_document(doc).unwrap();
-                    return Json(utxo);
-                }
-                None => {
+            match user_utxos(&utxo_coll, &utxo_req.public_key).await {
+                Ok(utxo) => Json(utxo),
+                Err(_) => {
                     let utxo = UTXO {
                         public_key: utxo_req.public_key,
                         utxos: Vec::new(),
                     };
-                    return Json(utxo);
+                    Json(utxo)
                 }
             }
         }